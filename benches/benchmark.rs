@@ -1,17 +1,41 @@
 use criterion::{criterion_group, criterion_main, Criterion};
-use lazyk_rust::LazyKProgram;
+use lazyk_rust::{LazyKProgram, RunnerConfig};
 
-fn parse_and_run(source: &str, input: &str) -> () {
+fn parse_and_run(source: &str, input: &str) {
     let mut program = LazyKProgram::compile(source).unwrap();
     program.run_string(input).unwrap();
 }
 
+// Runs program that never halts, until it prints `output_limit` bytes.
+// Garbage collector is set to run often, so freed slots are reused.
+fn parse_and_run_limited(source: &str, output_limit: usize) {
+    let config = RunnerConfig {
+        gc_threshold_bytes: 256 * 1024,
+        ..RunnerConfig::default()
+    };
+    let mut program = LazyKProgram::compile_with_config(source, config).unwrap();
+    program.set_output_limit(Some(output_limit));
+    program.run_vec(Vec::new()).unwrap();
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
     let source = include_str!("../examples/reverse.lazy");
     let input = "abcde12345".repeat(100);
-    c.bench_function("reverse 1000", |b| {
-        b.iter(|| parse_and_run(&source, &input))
+    c.bench_function("reverse 1000", |b| b.iter(|| parse_and_run(source, &input)));
+
+    // Allocation-heavy programs, which spend most of the time creating
+    // expressions that quickly become garbage.
+    let mut group = c.benchmark_group("allocation");
+    group.sample_size(20);
+    let source = include_str!("../examples/primes.lazy");
+    group.bench_function("primes 500", |b| {
+        b.iter(|| parse_and_run_limited(source, 500))
+    });
+    let source = include_str!("../examples/ab.lazy");
+    group.bench_function("ab 50000", |b| {
+        b.iter(|| parse_and_run_limited(source, 50000))
     });
+    group.finish();
 }
 
 criterion_group!(benches, criterion_benchmark);
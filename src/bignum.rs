@@ -0,0 +1,87 @@
+use std::fmt;
+
+/// Arbitrary-precision non-negative integer, as decoded from Church numerals.
+///
+/// ```
+/// use lazyk_rust::BigUint;
+/// let mut x = BigUint::from(u64::MAX);
+/// assert_eq!(x.to_u64(), Some(u64::MAX));
+/// x.increment();
+/// assert_eq!(x.to_u64(), None);
+/// assert_eq!(x.to_string(), "18446744073709551616");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BigUint {
+    // Little-endian, without trailing zeros.
+    limbs: Vec<u64>,
+}
+
+impl BigUint {
+    pub fn increment(&mut self) {
+        for limb in &mut self.limbs {
+            if *limb == u64::MAX {
+                *limb = 0;
+            } else {
+                *limb += 1;
+                return;
+            }
+        }
+        self.limbs.push(1);
+    }
+
+    /// Returns value if it fits in `u64`.
+    pub fn to_u64(&self) -> Option<u64> {
+        match self.limbs[..] {
+            [] => Some(0),
+            [limb] => Some(limb),
+            _ => None,
+        }
+    }
+
+    /// Returns 64-bit digits of the number, least significant first.
+    pub fn limbs(&self) -> &[u64] {
+        &self.limbs
+    }
+}
+
+impl From<u64> for BigUint {
+    fn from(value: u64) -> Self {
+        let mut limbs = Vec::new();
+        if value != 0 {
+            limbs.push(value);
+        }
+        Self { limbs }
+    }
+}
+
+// Largest power of 10 that fits in `u64`.
+static DECIMAL_BASE: u64 = 10_000_000_000_000_000_000;
+
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Split into groups of 19 decimal digits, least significant first.
+        let mut groups = Vec::new();
+        let mut limbs = self.limbs.clone();
+        while !limbs.is_empty() {
+            let mut remainder: u128 = 0;
+            for limb in limbs.iter_mut().rev() {
+                let value = (remainder << 64) | *limb as u128;
+                *limb = (value / DECIMAL_BASE as u128) as u64;
+                remainder = value % DECIMAL_BASE as u128;
+            }
+            groups.push(remainder as u64);
+            while limbs.last() == Some(&0) {
+                limbs.pop();
+            }
+        }
+        match groups.split_last() {
+            None => write!(f, "0"),
+            Some((first, rest)) => {
+                write!(f, "{}", first)?;
+                rest.iter()
+                    .rev()
+                    .try_for_each(|group| write!(f, "{:019}", group))
+            }
+        }
+    }
+}
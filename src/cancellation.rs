@@ -0,0 +1,42 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Handle that stops a running program from another thread.
+///
+/// The token is shared by all its clones. Once cancelled, every run of a
+/// program using it fails with `LazyKError::Cancelled`, until it's reset.
+///
+/// ```
+/// use lazyk_rust::{CancellationToken, LazyKError, LazyKProgram};
+/// let mut program = LazyKProgram::compile("SII(SII)").unwrap();
+/// let token = CancellationToken::new();
+/// program.set_cancellation_token(Some(token.clone()));
+/// let canceller = std::thread::spawn(move || token.cancel());
+/// assert!(matches!(program.run_vec(vec![]), Err(LazyKError::Cancelled)));
+/// canceller.join().unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Makes the token not cancelled, so programs using it can run again.
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,507 @@
+use crate::{
+    encoding::{encode_stream, encode_u64},
+    error::Result,
+    expression::{Expr, ExprId},
+    lambda::{abstract_var, apply, Term},
+    optimizer::Optimizer,
+    runner::LazyKRunner,
+};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+// Numbers of rules defined once and referred to by name, tried when looking
+// for the smallest program. Every definition makes the rest of the program
+// bigger, so defining all rules is rarely best.
+static RULE_SHARING_LIMITS: [usize; 8] = [0, 1, 2, 4, 8, 16, 32, 64];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Symbol {
+    Byte(u8),
+    Rule(usize),
+}
+
+// Symbol repeated given number of times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Run {
+    symbol: Symbol,
+    count: usize,
+}
+
+/// Grammar producing a byte string: the string is `top` with every rule
+/// replaced by its expansion. Rules only refer to rules with lower indices.
+struct Grammar {
+    top: Vec<Run>,
+    rules: Vec<Vec<Run>>,
+}
+
+impl Grammar {
+    /// Builds grammar with Re-Pair algorithm: while some pair of adjacent
+    /// runs occurs at least twice, replaces the most frequent one with a new
+    /// rule. Adjacent runs of the same symbol are merged after every
+    /// replacement. Rules that end up used once are then expanded in place.
+    fn new(bytes: &[u8]) -> Self {
+        let mut re_pair = RePair::new(bytes);
+        let mut pairs: Vec<[Run; 2]> = Vec::new();
+        while let Some(pair) = re_pair.most_frequent_pair() {
+            let rule = Run {
+                symbol: Symbol::Rule(pairs.len()),
+                count: 1,
+            };
+            re_pair.replace(pair, rule);
+            pairs.push(pair);
+        }
+        let sequence = re_pair.runs();
+
+        // Rules used once, not repeated, are expanded in place.
+        let mut uses = vec![0; pairs.len()];
+        let mut repeated = vec![false; pairs.len()];
+        for run in pairs.iter().flatten().chain(&sequence) {
+            if let Symbol::Rule(rule) = run.symbol {
+                uses[rule] += 1;
+                repeated[rule] |= run.count > 1;
+            }
+        }
+        let inlined: Vec<bool> = (0..pairs.len())
+            .map(|rule| uses[rule] == 1 && !repeated[rule])
+            .collect();
+        let mut bodies: Vec<Vec<Run>> = Vec::with_capacity(pairs.len());
+        let mut indices: HashMap<usize, usize> = HashMap::new();
+        let expand = |runs: &[Run], bodies: &[Vec<Run>], indices: &HashMap<usize, usize>| {
+            let mut result = Vec::new();
+            for &run in runs {
+                match run.symbol {
+                    Symbol::Rule(rule) if inlined[rule] => bodies[rule]
+                        .iter()
+                        .for_each(|&run| push_run(&mut result, run)),
+                    Symbol::Rule(rule) => push_run(
+                        &mut result,
+                        Run {
+                            symbol: Symbol::Rule(indices[&rule]),
+                            count: run.count,
+                        },
+                    ),
+                    Symbol::Byte(_) => push_run(&mut result, run),
+                }
+            }
+            result
+        };
+        let mut rules = Vec::new();
+        for (rule, pair) in pairs.iter().enumerate() {
+            let body = expand(pair, &bodies, &indices);
+            if !inlined[rule] {
+                indices.insert(rule, rules.len());
+                rules.push(body.clone());
+            }
+            bodies.push(body);
+        }
+        Self {
+            top: expand(&sequence, &bodies, &indices),
+            rules,
+        }
+    }
+
+    // Returns rules used more than once, sorted by how much defining them
+    // once could save, estimated as number of bytes not written.
+    fn rules_by_saving(&self) -> Vec<usize> {
+        let mut lengths: Vec<usize> = Vec::with_capacity(self.rules.len());
+        for body in &self.rules {
+            let length = body
+                .iter()
+                .map(|run| run.count * self.symbol_length(run.symbol, &lengths))
+                .sum();
+            lengths.push(length);
+        }
+        let mut uses = vec![0; self.rules.len()];
+        for run in self.rules.iter().flatten().chain(&self.top) {
+            if let Symbol::Rule(rule) = run.symbol {
+                uses[rule] += 1;
+            }
+        }
+        let mut rules: Vec<usize> = (0..self.rules.len())
+            .filter(|&rule| uses[rule] > 1)
+            .collect();
+        rules.sort_by_key(|&rule| std::cmp::Reverse((uses[rule] - 1) * lengths[rule]));
+        rules
+    }
+
+    fn symbol_length(&self, symbol: Symbol, rule_lengths: &[usize]) -> usize {
+        match symbol {
+            Symbol::Byte(_) => 1,
+            Symbol::Rule(rule) => rule_lengths[rule],
+        }
+    }
+}
+
+// Run in the sequence compressed by `RePair`.
+struct Node {
+    run: Run,
+    prev: Option<usize>,
+    next: Option<usize>,
+    removed: bool,
+}
+
+struct PairStats {
+    // Number of occurrences in the sequence. Adjacent runs have different
+    // symbols, so occurrences of a pair never overlap.
+    count: usize,
+    // Position of the pair in `RePair::pair_list`.
+    order: usize,
+    // Nodes where the pair started at some point. Some of them may no longer
+    // start it, so they are checked before use.
+    occurrences: Vec<usize>,
+}
+
+/// Sequence of runs compressed by Re-Pair algorithm. It is a linked list,
+/// so pairs are replaced in place, and counts of adjacent pairs are updated
+/// only around the replaced ones.
+struct RePair {
+    nodes: Vec<Node>,
+    pairs: HashMap<[Run; 2], PairStats>,
+    // Pairs in order in which they first appeared.
+    pair_list: Vec<[Run; 2]>,
+    // Count and order of pairs that occur at least twice. Entries are not
+    // updated when a count decreases, so they are checked when popped.
+    queue: BinaryHeap<(usize, Reverse<usize>)>,
+}
+
+impl RePair {
+    fn new(bytes: &[u8]) -> Self {
+        let symbols: Vec<Symbol> = bytes.iter().map(|&byte| Symbol::Byte(byte)).collect();
+        let runs = to_runs(&symbols);
+        let len = runs.len();
+        let nodes = runs
+            .into_iter()
+            .enumerate()
+            .map(|(index, run)| Node {
+                run,
+                prev: index.checked_sub(1),
+                next: Some(index + 1).filter(|&next| next < len),
+                removed: false,
+            })
+            .collect();
+        let mut re_pair = Self {
+            nodes,
+            pairs: HashMap::new(),
+            pair_list: Vec::new(),
+            queue: BinaryHeap::new(),
+        };
+        for node in 0..len {
+            re_pair.add_pair(node);
+        }
+        re_pair
+    }
+
+    // Returns pair of runs that starts at given node, if there is one.
+    fn pair_at(&self, node: usize) -> Option<[Run; 2]> {
+        let Node {
+            run, next, removed, ..
+        } = self.nodes[node];
+        match next {
+            Some(next) if !removed => Some([run, self.nodes[next].run]),
+            _ => None,
+        }
+    }
+
+    // Counts the pair that starts at given node.
+    fn add_pair(&mut self, node: usize) {
+        let pair = match self.pair_at(node) {
+            Some(pair) => pair,
+            None => return,
+        };
+        let order = self.pair_list.len();
+        let stats = self.pairs.entry(pair).or_insert(PairStats {
+            count: 0,
+            order,
+            occurrences: Vec::new(),
+        });
+        if stats.order == order {
+            self.pair_list.push(pair);
+        }
+        stats.count += 1;
+        stats.occurrences.push(node);
+        if stats.count >= 2 {
+            self.queue.push((stats.count, Reverse(stats.order)));
+        }
+    }
+
+    // Stops counting the pair that starts at given node, before it changes.
+    fn remove_pair(&mut self, node: usize) {
+        if let Some(pair) = self.pair_at(node) {
+            self.pairs.get_mut(&pair).expect("Pair is counted.").count -= 1;
+        }
+    }
+
+    // Finds pair of adjacent runs that occurs most often, if it occurs at
+    // least twice. Of equally frequent pairs, returns the one that appeared
+    // first.
+    fn most_frequent_pair(&mut self) -> Option<[Run; 2]> {
+        while let Some((count, Reverse(order))) = self.queue.pop() {
+            let pair = self.pair_list[order];
+            let current = self.pairs[&pair].count;
+            if current == count {
+                return Some(pair);
+            }
+            // Entry with the higher count was pushed when it increased.
+            if current < count && current >= 2 {
+                self.queue.push((current, Reverse(order)));
+            }
+        }
+        None
+    }
+
+    // Replaces every occurrence of pair with the rule, merging the rule with
+    // adjacent runs of the same rule.
+    fn replace(&mut self, pair: [Run; 2], rule: Run) {
+        let stats = self.pairs.get_mut(&pair).expect("Pair is counted.");
+        for node in std::mem::take(&mut stats.occurrences) {
+            if self.pair_at(node) != Some(pair) {
+                continue;
+            }
+            let prev = self.nodes[node].prev;
+            let next = self.nodes[node].next.expect("Pair has second run.");
+            if let Some(prev) = prev {
+                self.remove_pair(prev);
+            }
+            self.remove_pair(node);
+            self.remove_pair(next);
+            self.nodes[node].run = rule;
+            self.remove_node(next);
+
+            let mut node = node;
+            if let Some(prev) = prev.filter(|&prev| self.nodes[prev].run.symbol == rule.symbol) {
+                if let Some(before_prev) = self.nodes[prev].prev {
+                    self.remove_pair(before_prev);
+                }
+                self.nodes[prev].run.count += self.nodes[node].run.count;
+                self.remove_node(node);
+                node = prev;
+            }
+            if let Some(next) = self.nodes[node]
+                .next
+                .filter(|&next| self.nodes[next].run.symbol == rule.symbol)
+            {
+                self.remove_pair(next);
+                self.nodes[node].run.count += self.nodes[next].run.count;
+                self.remove_node(next);
+            }
+            if let Some(prev) = self.nodes[node].prev {
+                self.add_pair(prev);
+            }
+            self.add_pair(node);
+        }
+    }
+
+    fn remove_node(&mut self, node: usize) {
+        let Node { prev, next, .. } = self.nodes[node];
+        if let Some(prev) = prev {
+            self.nodes[prev].next = next;
+        }
+        if let Some(next) = next {
+            self.nodes[next].prev = prev;
+        }
+        self.nodes[node].removed = true;
+    }
+
+    // Returns the sequence. The first run is never removed, as pairs are
+    // replaced in place of their first run and merged into preceding runs.
+    fn runs(&self) -> Vec<Run> {
+        let mut runs = Vec::new();
+        let mut node = if self.nodes.is_empty() { None } else { Some(0) };
+        while let Some(index) = node {
+            runs.push(self.nodes[index].run);
+            node = self.nodes[index].next;
+        }
+        runs
+    }
+}
+
+// Appends run to a sequence, merging it with the last run of the same symbol.
+fn push_run(runs: &mut Vec<Run>, run: Run) {
+    match runs.last_mut() {
+        Some(last) if last.symbol == run.symbol => last.count += run.count,
+        _ => runs.push(run),
+    }
+}
+
+fn to_runs(symbols: &[Symbol]) -> Vec<Run> {
+    let mut runs = Vec::new();
+    for &symbol in symbols {
+        push_run(&mut runs, Run { symbol, count: 1 });
+    }
+    runs
+}
+
+/// Builds programs printing a byte string from its grammar.
+///
+/// Every symbol becomes a function that prepends its expansion to a list:
+/// a byte `b` is `\t. cons b t`, a sequence is composition of its symbols
+/// and a run of `n` symbols is Church numeral `n` applied to the symbol.
+/// Shared rules are defined once and passed to the rest of the program as
+/// arguments.
+struct Compiler<'a> {
+    runner: &'a mut LazyKRunner,
+    grammar: &'a Grammar,
+    // Rules defined once, the others are written everywhere they are used.
+    shared_rules: HashSet<usize>,
+}
+
+impl Compiler<'_> {
+    fn compile(&mut self) -> Result<ExprId> {
+        let top = self.sequence(&self.grammar.top)?;
+        let end = Term::Const(encode_stream(self.runner, &[])?);
+        let output = apply(self.runner, top, end)?;
+        let k = Term::Const(self.runner.k);
+        let mut program = apply(self.runner, k, output)?;
+
+        // Rules may refer to rules with lower indices, so those are defined
+        // outside them.
+        let mut shared_rules: Vec<usize> = self.shared_rules.iter().copied().collect();
+        shared_rules.sort();
+        for rule in shared_rules.into_iter().rev() {
+            let function = abstract_var(self.runner, &rule_name(rule), program)?;
+            let definition = self.sequence(&self.grammar.rules[rule])?;
+            program = apply(self.runner, function, definition)?;
+        }
+        match program {
+            Term::Const(expr_id) => Ok(expr_id),
+            _ => unreachable!("All names are defined."),
+        }
+    }
+
+    fn sequence(&mut self, runs: &[Run]) -> Result<Term> {
+        let mut terms = Vec::with_capacity(runs.len());
+        for run in runs {
+            let mut term = self.symbol(run.symbol)?;
+            if run.count > 1 {
+                let count = Term::Const(encode_u64(self.runner, run.count as u64)?);
+                term = apply(self.runner, count, term)?;
+            }
+            terms.push(term);
+        }
+        self.compose_all(terms)
+    }
+
+    // Composes functions in a balanced tree, as the term is shallower than
+    // a chain and so faster to abstract variables from.
+    fn compose_all(&mut self, mut terms: Vec<Term>) -> Result<Term> {
+        match terms.len() {
+            0 => Ok(Term::Const(self.runner.i)),
+            1 => Ok(terms.pop().expect("There is one term.")),
+            len => {
+                let rest = terms.split_off(len / 2);
+                let first = self.compose_all(terms)?;
+                let rest = self.compose_all(rest)?;
+                self.compose(first, rest)
+            }
+        }
+    }
+
+    fn symbol(&mut self, symbol: Symbol) -> Result<Term> {
+        match symbol {
+            Symbol::Byte(byte) => self.prepend_byte(byte),
+            Symbol::Rule(rule) if self.shared_rules.contains(&rule) => {
+                Ok(Term::Var(rule_name(rule)))
+            }
+            Symbol::Rule(rule) => self.sequence(&self.grammar.rules[rule]),
+        }
+    }
+
+    // \t. cons byte t, that is S(K(S(SI(K byte))))K.
+    fn prepend_byte(&mut self, byte: u8) -> Result<Term> {
+        let runner = &mut *self.runner;
+        let k_byte = runner.new_expr(Expr::K1(runner.church_char(byte as u16)))?;
+        let cons_byte = runner.new_expr(Expr::S2(runner.i, k_byte))?;
+        let s_cons_byte = runner.new_expr(Expr::S1(cons_byte))?;
+        let k_s_cons_byte = runner.new_expr(Expr::K1(s_cons_byte))?;
+        Ok(Term::Const(
+            runner.new_expr(Expr::S2(k_s_cons_byte, runner.k))?,
+        ))
+    }
+
+    // \x. f (g x), that is S(K f) g.
+    fn compose(&mut self, f: Term, g: Term) -> Result<Term> {
+        let (s, k) = (Term::Const(self.runner.s), Term::Const(self.runner.k));
+        let k_f = apply(self.runner, k, f)?;
+        let s_k_f = apply(self.runner, s, k_f)?;
+        apply(self.runner, s_k_f, g)
+    }
+}
+
+fn rule_name(rule: usize) -> String {
+    format!("r{}", rule)
+}
+
+/// Builds program that prints given bytes and is as small as it can find,
+/// by trying several ways to compress them. Size is measured as length of
+/// the source code in combinator-calculus style, so the result is never
+/// bigger than the plain list of bytes.
+pub(crate) fn compressed_printer(runner: &mut LazyKRunner, bytes: &[u8]) -> Result<ExprId> {
+    let list = encode_stream(runner, bytes)?;
+    let plain = runner.new_expr(Expr::K1(list))?;
+    let mut best = (source_length(runner, plain), plain);
+    let grammar = Grammar::new(bytes);
+    let rules_by_saving = grammar.rules_by_saving();
+    for limit in RULE_SHARING_LIMITS {
+        let limit = limit.min(rules_by_saving.len());
+        let mut compiler = Compiler {
+            runner,
+            grammar: &grammar,
+            shared_rules: rules_by_saving.iter().copied().take(limit).collect(),
+        };
+        let program = compiler.compile()?;
+        let program = Optimizer::new(runner).optimize(program)?;
+        let size = source_length(runner, program);
+        if size < best.0 {
+            best = (size, program);
+        }
+        if limit == rules_by_saving.len() {
+            break;
+        }
+    }
+    Ok(best.1)
+}
+
+// Length of source code printed by `CcPrinter`. Plain list of bytes is as
+// deep as it is long, so expression is walked with explicit stack.
+fn source_length(runner: &LazyKRunner, expr_id: ExprId) -> usize {
+    // Lengths of expressions written with and without parentheses.
+    let mut lengths: HashMap<(ExprId, bool), usize> = HashMap::new();
+    let mut stack = vec![(expr_id, false)];
+    while let Some(&(top, need_paren)) = stack.last() {
+        if lengths.contains_key(&(top, need_paren)) {
+            stack.pop();
+            continue;
+        }
+        // Length of combinator written before arguments, and the arguments.
+        let (own_length, args): (usize, Vec<(ExprId, bool)>) = match *runner.get_expr(top) {
+            Expr::S | Expr::K | Expr::I => (1, vec![]),
+            // Indirection left by reduction, equivalent to its argument.
+            Expr::I1(arg) => (0, vec![(arg, need_paren)]),
+            Expr::A(arg1, arg2) => (0, vec![(arg1, false), (arg2, true)]),
+            Expr::K1(arg) | Expr::S1(arg) => (1, vec![(arg, true)]),
+            Expr::S2(arg1, arg2) => (1, vec![(arg1, true), (arg2, true)]),
+            _ => panic!("Program is printable."),
+        };
+        let pending: Vec<(ExprId, bool)> = args
+            .iter()
+            .copied()
+            .filter(|arg| !lengths.contains_key(arg))
+            .collect();
+        if !pending.is_empty() {
+            stack.extend(pending);
+            continue;
+        }
+        let parens = match *runner.get_expr(top) {
+            Expr::A(_, _) | Expr::K1(_) | Expr::S1(_) | Expr::S2(_, _) if need_paren => 2,
+            _ => 0,
+        };
+        let length = args.iter().fold(own_length + parens, |length, arg| {
+            length.saturating_add(lengths[arg])
+        });
+        lengths.insert((top, need_paren), length);
+        stack.pop();
+    }
+    lengths[&(expr_id, false)]
+}
@@ -0,0 +1,295 @@
+use crate::{
+    bignum::BigUint,
+    error::{LazyKError, Result},
+    expression::{Expr, ExprId},
+    runner::LazyKRunner,
+};
+
+/// Rust value that can be converted to Lazy K expression.
+///
+/// Values are encoded as follows:
+/// * `bool` as Church boolean: `true` is `K`, `false` is `KI`;
+/// * unsigned integers and `BigUint` as Church numerals;
+/// * tuples as Church tuples: `(a, b)` is `\f. f a b`;
+/// * `Option` with Scott encoding: `None` is `\n s. n`, `Some(x)` is `\n s. s x`;
+/// * `Vec` and slices as Scott lists: empty list is `\n c. n`, list with head
+///   `x` and tail `xs` is `\n c. c x xs`;
+/// * strings as Lazy K streams, the same as program's input: Church pairs of
+///   bytes, terminated by numeral 256.
+pub trait ToLazyK {
+    /// Builds expression, failing with `LazyKError::OutOfMemory` when memory
+    /// limit of the runner is reached.
+    fn to_lazyk(&self, runner: &mut LazyKRunner) -> Result<ExprId>;
+}
+
+/// Rust value that can be decoded from Lazy K expression, with encoding
+/// described in `ToLazyK`.
+pub trait FromLazyK: Sized {
+    /// Evaluates expression as far as needed to decode it. All reductions
+    /// count towards the step budget of `LazyKProgram::call`.
+    fn from_lazyk(runner: &mut LazyKRunner, expr_id: ExprId) -> Result<Self>;
+}
+
+fn apply2(runner: &mut LazyKRunner, f: ExprId, x: ExprId, y: ExprId) -> Result<ExprId> {
+    let fx = runner.partial_apply(f, x)?;
+    runner.partial_apply(fx, y)
+}
+
+// Church numeral for m * n.
+fn multiply(runner: &mut LazyKRunner, m: ExprId, n: ExprId) -> Result<ExprId> {
+    // \f. m (n f)
+    let k_m = runner.new_expr(Expr::K1(m))?;
+    runner.new_expr(Expr::S2(k_m, n))
+}
+
+// Church numeral for m + n.
+fn add(runner: &mut LazyKRunner, m: ExprId, n: ExprId) -> Result<ExprId> {
+    // \f x. m f (n f x) = \f. S(K(m f))(n f) = S(S(KS)(S(KK)m))n
+    let k_s = runner.new_expr(Expr::K1(runner.s))?;
+    let k_k = runner.new_expr(Expr::K1(runner.k))?;
+    let k_m = runner.new_expr(Expr::S2(k_k, m))?;
+    let s_k_m = runner.new_expr(Expr::S2(k_s, k_m))?;
+    runner.new_expr(Expr::S2(s_k_m, n))
+}
+
+pub(crate) fn encode_u64(runner: &mut LazyKRunner, n: u64) -> Result<ExprId> {
+    if n <= 256 {
+        return Ok(runner.church_char(n as u16));
+    }
+    let high = encode_u64(runner, n / 256)?;
+    let high = multiply(runner, high, runner.church_char(256))?;
+    match n % 256 {
+        0 => Ok(high),
+        low => add(runner, high, runner.church_char(low as u16)),
+    }
+}
+
+/// Builds Lazy K stream containing given bytes.
+pub(crate) fn encode_stream(runner: &mut LazyKRunner, bytes: &[u8]) -> Result<ExprId> {
+    let eof = runner.church_char(256);
+    let mut list = runner.pair(eof, runner.k)?;
+    for &byte in bytes.iter().rev() {
+        list = runner.pair(runner.church_char(byte as u16), list)?;
+    }
+    Ok(list)
+}
+
+impl<T: ToLazyK + ?Sized> ToLazyK for &T {
+    fn to_lazyk(&self, runner: &mut LazyKRunner) -> Result<ExprId> {
+        (**self).to_lazyk(runner)
+    }
+}
+
+impl ToLazyK for bool {
+    fn to_lazyk(&self, runner: &mut LazyKRunner) -> Result<ExprId> {
+        if *self {
+            Ok(runner.k)
+        } else {
+            Ok(runner.ki)
+        }
+    }
+}
+
+impl FromLazyK for bool {
+    fn from_lazyk(runner: &mut LazyKRunner, expr_id: ExprId) -> Result<Self> {
+        // Boolean selects one of the arguments, which are in the preamble,
+        // so their ids don't change.
+        let e = apply2(runner, expr_id, runner.k, runner.ki)?;
+        match runner.whnf_expr(e)? {
+            id if id == runner.k => Ok(true),
+            id if id == runner.ki => Ok(false),
+            _ => Err(LazyKError::InvalidEncoding("boolean")),
+        }
+    }
+}
+
+macro_rules! impl_numeral {
+    ($($t:ty),*) => {
+        $(
+            impl ToLazyK for $t {
+                fn to_lazyk(&self, runner: &mut LazyKRunner) -> Result<ExprId> {
+                    encode_u64(runner, *self as u64)
+                }
+            }
+
+            impl FromLazyK for $t {
+                fn from_lazyk(runner: &mut LazyKRunner, expr_id: ExprId) -> Result<Self> {
+                    let count = runner.count_applications(expr_id, Some(<$t>::MAX as u64))?;
+                    Ok(count.to_u64().expect("Count is within the limit.") as $t)
+                }
+            }
+        )*
+    };
+}
+
+impl_numeral!(u8, u16, u32, u64, usize);
+
+impl ToLazyK for BigUint {
+    fn to_lazyk(&self, runner: &mut LazyKRunner) -> Result<ExprId> {
+        // 2^64, as exponentiation is application of numerals.
+        let base = runner.partial_apply(runner.church_char(64), runner.church_char(2))?;
+        let mut result = runner.church_char(0);
+        for &limb in self.limbs().iter().rev() {
+            let high = multiply(runner, result, base)?;
+            let low = encode_u64(runner, limb)?;
+            result = add(runner, high, low)?;
+        }
+        Ok(result)
+    }
+}
+
+impl FromLazyK for BigUint {
+    fn from_lazyk(runner: &mut LazyKRunner, expr_id: ExprId) -> Result<Self> {
+        runner.church2big(expr_id)
+    }
+}
+
+impl<T: ToLazyK> ToLazyK for Option<T> {
+    fn to_lazyk(&self, runner: &mut LazyKRunner) -> Result<ExprId> {
+        match self {
+            None => Ok(runner.k),
+            Some(value) => {
+                // K(SI(Kx))
+                let value = value.to_lazyk(runner)?;
+                let k_value = runner.new_expr(Expr::K1(value))?;
+                let apply_value = runner.new_expr(Expr::S2(runner.i, k_value))?;
+                runner.new_expr(Expr::K1(apply_value))
+            }
+        }
+    }
+}
+
+impl<T: FromLazyK> FromLazyK for Option<T> {
+    fn from_lazyk(runner: &mut LazyKRunner, expr_id: ExprId) -> Result<Self> {
+        // `None` returns the first argument, `Some(x)` reduces to `K x`.
+        let e = apply2(runner, expr_id, runner.zero, runner.k)?;
+        let result_id = runner.whnf_expr(e)?;
+        if result_id == runner.zero {
+            return Ok(None);
+        }
+        match *runner.get_expr(result_id) {
+            Expr::K1(value) => Ok(Some(T::from_lazyk(runner, value)?)),
+            _ => Err(LazyKError::InvalidEncoding("option")),
+        }
+    }
+}
+
+impl<T: ToLazyK> ToLazyK for [T] {
+    fn to_lazyk(&self, runner: &mut LazyKRunner) -> Result<ExprId> {
+        let mut list = runner.k;
+        for item in self.iter().rev() {
+            let item = item.to_lazyk(runner)?;
+            let pair = runner.pair(item, list)?;
+            list = runner.new_expr(Expr::K1(pair))?;
+        }
+        Ok(list)
+    }
+}
+
+impl<T: ToLazyK> ToLazyK for Vec<T> {
+    fn to_lazyk(&self, runner: &mut LazyKRunner) -> Result<ExprId> {
+        self.as_slice().to_lazyk(runner)
+    }
+}
+
+impl<T: FromLazyK> FromLazyK for Vec<T> {
+    fn from_lazyk(runner: &mut LazyKRunner, expr_id: ExprId) -> Result<Self> {
+        let mut items = Vec::new();
+        let mut list = expr_id;
+        loop {
+            // Empty list returns the first argument, other lists reduce to `S x xs`.
+            let e = apply2(runner, list, runner.zero, runner.s)?;
+            let result_id = runner.whnf_expr(e)?;
+            if result_id == runner.zero {
+                return Ok(items);
+            }
+            let (head, tail) = match *runner.get_expr(result_id) {
+                Expr::S2(head, tail) => (head, tail),
+                _ => return Err(LazyKError::InvalidEncoding("list")),
+            };
+            runner.push_root(tail);
+            let item = T::from_lazyk(runner, head);
+            list = runner.pop_root();
+            items.push(item?);
+        }
+    }
+}
+
+impl ToLazyK for str {
+    fn to_lazyk(&self, runner: &mut LazyKRunner) -> Result<ExprId> {
+        encode_stream(runner, self.as_bytes())
+    }
+}
+
+impl ToLazyK for String {
+    fn to_lazyk(&self, runner: &mut LazyKRunner) -> Result<ExprId> {
+        self.as_str().to_lazyk(runner)
+    }
+}
+
+impl FromLazyK for String {
+    fn from_lazyk(runner: &mut LazyKRunner, expr_id: ExprId) -> Result<Self> {
+        let mut bytes = Vec::new();
+        let mut list = expr_id;
+        loop {
+            let head = runner.car(list)?;
+            runner.push_root(list);
+            let byte = runner.church2int(head);
+            list = runner.pop_root();
+            match byte? {
+                byte if byte >= 256 => return Ok(String::from_utf8(bytes)?),
+                byte => bytes.push(byte as u8),
+            }
+            list = runner.cdr(list)?;
+        }
+    }
+}
+
+// Decodes field of a tuple with `size` fields, which is the last temporary root.
+fn decode_field<T: FromLazyK>(runner: &mut LazyKRunner, size: usize, index: usize) -> Result<T> {
+    // Selector ignores `index` arguments with K, then returns the next one,
+    // ignoring the rest: \x. K(f x) = S(KK)f ignores one more than f.
+    let k_k = runner.new_expr(Expr::K1(runner.k))?;
+    let mut selector = runner.i;
+    for _ in index + 1..size {
+        selector = runner.new_expr(Expr::S2(k_k, selector))?;
+    }
+    for _ in 0..index {
+        selector = runner.new_expr(Expr::K1(selector))?;
+    }
+    let tuple = runner.pop_root();
+    runner.push_root(tuple);
+    let field = runner.partial_apply(tuple, selector)?;
+    T::from_lazyk(runner, field)
+}
+
+macro_rules! impl_tuple {
+    ($size:expr; $($name:ident $index:tt),+) => {
+        impl<$($name: ToLazyK),+> ToLazyK for ($($name,)+) {
+            fn to_lazyk(&self, runner: &mut LazyKRunner) -> Result<ExprId> {
+                // Builds S(...(S(SI(Ka))(Kb))...)(Kz).
+                let mut tuple = runner.i;
+                $(
+                    let field = self.$index.to_lazyk(runner)?;
+                    let k_field = runner.new_expr(Expr::K1(field))?;
+                    tuple = runner.new_expr(Expr::S2(tuple, k_field))?;
+                )+
+                Ok(tuple)
+            }
+        }
+
+        impl<$($name: FromLazyK),+> FromLazyK for ($($name,)+) {
+            fn from_lazyk(runner: &mut LazyKRunner, expr_id: ExprId) -> Result<Self> {
+                runner.push_root(expr_id);
+                let mut decode = || Ok(($(decode_field::<$name>(runner, $size, $index)?,)+));
+                let result = decode();
+                runner.pop_root();
+                result
+            }
+        }
+    };
+}
+
+impl_tuple!(2; A 0, B 1);
+impl_tuple!(3; A 0, B 1, C 2);
+impl_tuple!(4; A 0, B 1, C 2, D 3);
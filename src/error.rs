@@ -0,0 +1,140 @@
+use std::{fmt, io, string::FromUtf8Error};
+
+/// Error produced when compiling, running or printing LazyK program.
+#[derive(Debug)]
+pub enum LazyKError {
+    /// Source code is not a valid LazyK program.
+    Parse(ParseError),
+    /// Expression which was expected to be a Church numeral is not one.
+    NotANumeral,
+    /// Church numeral is too large to be decoded.
+    NumeralOverflow,
+    /// Reading input or writing output failed.
+    Io(io::Error),
+    /// Output of a program is not a valid UTF-8 string.
+    InvalidUtf8(FromUtf8Error),
+    /// Expression contains nodes that have no representation in source code.
+    UnprintableExpression,
+    /// Reduction budget was exhausted before program halted.
+    BudgetExhausted,
+    /// Attempted to resume a run that was not started or has already finished.
+    NoRunToResume,
+    /// String is not a name of any source code style.
+    UnknownStyle(String),
+    /// Running program needed more memory than allowed by `RunnerConfig`.
+    OutOfMemory,
+    /// Expression doesn't encode a value of expected kind.
+    InvalidEncoding(&'static str),
+    /// Evaluation was stopped by `CancellationToken`.
+    Cancelled,
+    /// Evaluation took longer than allowed by `LazyKProgram::set_timeout`.
+    TimedOut,
+}
+
+pub type Result<T> = std::result::Result<T, LazyKError>;
+
+/// Error in LazyK source code, with location where it was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Description of the error.
+    pub message: String,
+    /// Line number, starting from 1.
+    pub line: usize,
+    /// Column number (counted in characters), starting from 1.
+    pub column: usize,
+    /// Offset in bytes from the beginning of the source.
+    pub offset: usize,
+    /// Text of the line where the error was found.
+    pub source_line: String,
+}
+
+// Maximal number of characters of source line shown by `ParseError::snippet`.
+static SNIPPET_WIDTH: usize = 80;
+
+impl ParseError {
+    pub(crate) fn new(message: String, source: &str, offset: usize) -> Self {
+        let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[offset..]
+            .find('\n')
+            .map_or(source.len(), |i| offset + i);
+        let source_line = source[line_start..line_end].trim_end_matches('\r');
+        // Error after trailing '\r' is shown at the end of the line.
+        let column = source[line_start..offset]
+            .chars()
+            .count()
+            .min(source_line.chars().count())
+            + 1;
+        Self {
+            message,
+            line: source[..offset].matches('\n').count() + 1,
+            column,
+            offset,
+            source_line: source_line.to_string(),
+        }
+    }
+
+    /// Returns the line where the error was found, followed by a line with
+    /// a caret pointing at the error location.
+    ///
+    /// Long lines are cut to show only the part around the error.
+    pub fn snippet(&self) -> String {
+        let chars: Vec<char> = self.source_line.chars().collect();
+        let start = (self.column - 1).saturating_sub(SNIPPET_WIDTH / 2);
+        let end = chars.len().min(start + SNIPPET_WIDTH);
+        let line: String = chars[start..end].iter().collect();
+        // Keep tabs, so the caret is aligned however they are displayed.
+        let padding: String = chars[start..self.column - 1]
+            .iter()
+            .map(|&ch| if ch == '\t' { '\t' } else { ' ' })
+            .collect();
+        format!("{}\n{}^", line, padding)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl fmt::Display for LazyKError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(err) => write!(f, "{}", err),
+            Self::NotANumeral => write!(f, "Program's output is not a church numeral."),
+            Self::NumeralOverflow => write!(f, "Church numeral is too large."),
+            Self::Io(err) => write!(f, "I/O error: {}", err),
+            Self::InvalidUtf8(err) => write!(f, "Output is not valid UTF-8: {}", err),
+            Self::UnprintableExpression => write!(f, "Encountered unprintable expression type."),
+            Self::BudgetExhausted => write!(f, "Reduction budget exhausted."),
+            Self::NoRunToResume => write!(f, "There is no run to resume."),
+            Self::UnknownStyle(name) => write!(f, "Unknown style: {}.", name),
+            Self::OutOfMemory => write!(f, "Memory limit exceeded."),
+            Self::InvalidEncoding(kind) => write!(f, "Expression is not a valid {}.", kind),
+            Self::Cancelled => write!(f, "Evaluation was cancelled."),
+            Self::TimedOut => write!(f, "Evaluation timed out."),
+        }
+    }
+}
+
+impl std::error::Error for LazyKError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::InvalidUtf8(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LazyKError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<FromUtf8Error> for LazyKError {
+    fn from(err: FromUtf8Error) -> Self {
+        Self::InvalidUtf8(err)
+    }
+}
@@ -1,5 +1,6 @@
 pub type ExprId = u32;
 
+#[derive(Clone, Copy)]
 pub enum Expr {
     A(ExprId, ExprId),
     K,
@@ -11,8 +12,11 @@ pub enum Expr {
     I1(ExprId),
     LazyRead,
     Inc,
+    // Behaves like I, but counts how many times it was applied.
+    Count,
     Num(u16),
-    Free,
+    // Unused slot, linked to the next one in the free list (zero ends the list).
+    Free(ExprId),
 }
 
 #[test]
@@ -1,38 +1,170 @@
-use anyhow::Result;
-use byteorder::{ReadBytesExt, WriteBytesExt};
-use std::io::{BufRead, ErrorKind, Write};
-
-pub enum Input {
-    Null,
-    Reader(Box<dyn BufRead + 'static>),
-}
-impl Input {
-    pub fn read_byte(&mut self) -> Option<u8> {
-        match self {
-            Input::Null => None,
-            Input::Reader(reader) => match reader.as_mut().read_u8() {
-                Ok(ch) => Some(ch),
-                Err(err) if err.kind() == ErrorKind::UnexpectedEof => None,
-                Err(err) => panic!("I/O error: {}", err),
-            },
-        }
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    sync::mpsc::{Receiver, Sender},
+    task::{Context, Poll},
+};
+
+/// Source of bytes that a running program reads as its input.
+///
+/// Implemented for `ReadSource` (any `Read`), `VecDeque<u8>`, channel
+/// receivers, `io::Empty` and closures returning the next byte.
+///
+/// ```
+/// use lazyk_rust::LazyKProgram;
+/// use std::collections::VecDeque;
+/// let mut program = LazyKProgram::compile("I").unwrap();
+/// let input = VecDeque::from(b"abc".to_vec());
+/// let mut output = String::new();
+/// let mut sink = |byte: u8| -> std::io::Result<()> {
+///     output.push(byte.to_ascii_uppercase() as char);
+///     Ok(())
+/// };
+/// program.run_with(input, &mut sink).unwrap();
+/// assert_eq!(output, "ABC");
+/// ```
+pub trait ByteSource {
+    /// Returns the next byte, or None at the end of input.
+    fn read_byte(&mut self) -> io::Result<Option<u8>>;
+}
+
+/// Source of bytes read by a program run with `LazyKProgram::run_async`,
+/// which may not have the next byte available yet, like `AsyncRead`.
+pub trait AsyncByteSource {
+    /// Returns the next byte, or None at the end of input. If it's not
+    /// available yet, returns `Poll::Pending` and arranges for the waker of
+    /// `cx` to be called when it is.
+    fn poll_read_byte(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Option<u8>>>;
+}
+
+/// Destination of bytes that a running program writes as its output.
+///
+/// Implemented for `WriteSink` (any `Write`), `Vec<u8>`, channel senders,
+/// `io::Sink` and closures taking a byte.
+pub trait ByteSink {
+    fn write_byte(&mut self, byte: u8) -> io::Result<()>;
+
+    /// Called when program halts, after the last byte is written.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
     }
 }
 
-pub enum Output {
-    Null,
-    Buffer(Vec<u8>),
-    Writer(Box<dyn Write + 'static>),
+/// Reads bytes from a `Read`, buffering them.
+pub struct ReadSource<R: Read> {
+    reader: BufReader<R>,
 }
-impl Output {
-    pub fn write_char(&mut self, c: u8) -> Result<()> {
-        match self {
-            Self::Null => Ok(()),
-            Self::Buffer(buf) => {
-                buf.push(c);
-                Ok(())
+
+impl<R: Read> ReadSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+        }
+    }
+}
+
+impl<R: Read> ByteSource for ReadSource<R> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        loop {
+            match self.reader.fill_buf() {
+                Ok([]) => return Ok(None),
+                Ok(buf) => {
+                    let byte = buf[0];
+                    self.reader.consume(1);
+                    return Ok(Some(byte));
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
             }
-            Self::Writer(w) => w.write_u8(c).map_err(anyhow::Error::from),
         }
     }
 }
+
+/// Writes bytes to a `Write`, flushing it when program halts.
+pub struct WriteSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> WriteSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> ByteSink for WriteSink<W> {
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.writer.write_all(&[byte])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl ByteSource for VecDeque<u8> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.pop_front())
+    }
+}
+
+impl AsyncByteSource for VecDeque<u8> {
+    fn poll_read_byte(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<Option<u8>>> {
+        Poll::Ready(Ok(self.pop_front()))
+    }
+}
+
+impl ByteSource for io::Empty {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        Ok(None)
+    }
+}
+
+impl AsyncByteSource for io::Empty {
+    fn poll_read_byte(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<Option<u8>>> {
+        Poll::Ready(Ok(None))
+    }
+}
+
+/// Input ends when all senders are dropped.
+impl ByteSource for Receiver<u8> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.recv().ok())
+    }
+}
+
+impl<F: FnMut() -> io::Result<Option<u8>>> ByteSource for F {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        self()
+    }
+}
+
+impl ByteSink for Vec<u8> {
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.push(byte);
+        Ok(())
+    }
+}
+
+impl ByteSink for io::Sink {
+    fn write_byte(&mut self, _byte: u8) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Fails with `ErrorKind::BrokenPipe` when the receiver is dropped.
+impl ByteSink for Sender<u8> {
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.send(byte)
+            .map_err(|_| io::Error::new(ErrorKind::BrokenPipe, "Receiver was dropped."))
+    }
+}
+
+impl<F: FnMut(u8) -> io::Result<()>> ByteSink for F {
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self(byte)
+    }
+}
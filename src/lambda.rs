@@ -0,0 +1,93 @@
+use crate::{error::Result, expression::ExprId, runner::LazyKRunner};
+
+/// Term of lambda calculus, already compiled to combinators except for free variables.
+///
+/// Closed subterms are always folded to `Const`, so a term without free
+/// variables is always a `Const`.
+pub(crate) enum Term {
+    Const(ExprId),
+    Var(String),
+    App(Box<Term>, Box<Term>),
+}
+
+impl Term {
+    fn has_free(&self, var: &str) -> bool {
+        let mut stack = vec![self];
+        while let Some(term) = stack.pop() {
+            match term {
+                Term::Const(_) => {}
+                Term::Var(name) if name == var => return true,
+                Term::Var(_) => {}
+                Term::App(lhs, rhs) => stack.extend([&**rhs, &**lhs]),
+            }
+        }
+        false
+    }
+}
+
+/// Applies `lhs` to `rhs`, folding closed result to a single expression.
+pub(crate) fn apply(pool: &mut LazyKRunner, lhs: Term, rhs: Term) -> Result<Term> {
+    Ok(match (lhs, rhs) {
+        (Term::Const(lhs), Term::Const(rhs)) => Term::Const(pool.partial_apply(lhs, rhs)?),
+        (lhs, rhs) => Term::App(Box::new(lhs), Box::new(rhs)),
+    })
+}
+
+/// Bracket abstraction: builds term `[var]body`, which doesn't contain `var`,
+/// such that `([var]body) X` reduces to `body` with `X` substituted for `var`.
+///
+/// Uses the following rules, in order:
+/// * `[x]E = K E`, if `x` is not free in `E`;
+/// * `[x]x = I`;
+/// * `[x](M x) = M`, if `x` is not free in `M` (eta reduction);
+/// * `[x](M N) = S (K M) ([x]N)`, if `x` is not free in `M` (B combinator);
+/// * `[x](M N) = S ([x]M) (K N)`, if `x` is not free in `N` (C combinator);
+/// * `[x](M N) = S ([x]M) ([x]N)`.
+pub(crate) fn abstract_var(pool: &mut LazyKRunner, var: &str, body: Term) -> Result<Term> {
+    // Terms abstracted from many variables are deep, so they are visited
+    // with explicit stack rather than recursion.
+    enum Task {
+        Abstract(Term),
+        // Applies given term to the last result.
+        ApplyTo(Term),
+        // Applies S to the last two results.
+        ApplyS,
+    }
+    let mut tasks = vec![Task::Abstract(body)];
+    let mut results: Vec<Term> = Vec::new();
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Abstract(body) if !body.has_free(var) => {
+                results.push(apply(pool, Term::Const(pool.k), body)?)
+            }
+            Task::Abstract(Term::Var(_)) => results.push(Term::Const(pool.i)),
+            Task::Abstract(Term::App(lhs, rhs)) => match (lhs.has_free(var), *rhs) {
+                (false, Term::Var(_)) => results.push(*lhs),
+                (false, rhs) => {
+                    let k_lhs = apply(pool, Term::Const(pool.k), *lhs)?;
+                    let s_lhs = apply(pool, Term::Const(pool.s), k_lhs)?;
+                    tasks.push(Task::ApplyTo(s_lhs));
+                    tasks.push(Task::Abstract(rhs));
+                }
+                // Abstracting from `rhs` without `var` gives `K rhs`.
+                (true, rhs) => {
+                    tasks.push(Task::ApplyS);
+                    tasks.push(Task::Abstract(rhs));
+                    tasks.push(Task::Abstract(*lhs));
+                }
+            },
+            Task::Abstract(Term::Const(_)) => unreachable!("Constant has no free variables."),
+            Task::ApplyTo(lhs) => {
+                let rhs = results.pop().expect("Argument is abstracted.");
+                results.push(apply(pool, lhs, rhs)?);
+            }
+            Task::ApplyS => {
+                let rhs = results.pop().expect("Argument is abstracted.");
+                let lhs = results.pop().expect("Function is abstracted.");
+                let s_lhs = apply(pool, Term::Const(pool.s), lhs)?;
+                results.push(apply(pool, s_lhs, rhs)?);
+            }
+        }
+    }
+    Ok(results.pop().expect("Body is abstracted."))
+}
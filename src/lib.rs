@@ -1,11 +1,42 @@
+mod bignum;
+mod cancellation;
+mod compressor;
+mod encoding;
+mod error;
 mod expression;
 mod io;
+mod lambda;
+mod optimizer;
 mod parser;
 mod printer;
 mod program;
+mod run_future;
 mod runner;
+mod short_printer;
+mod term;
 mod util;
 
+pub use bignum::BigUint;
+pub use cancellation::CancellationToken;
+pub use encoding::FromLazyK;
+pub use encoding::ToLazyK;
+pub use error::LazyKError;
+pub use error::ParseError;
+pub use expression::ExprId;
+pub use io::AsyncByteSource;
+pub use io::ByteSink;
+pub use io::ByteSource;
+pub use io::ReadSource;
+pub use io::WriteSink;
+pub use optimizer::Optimization;
 pub use program::LazyKProgram;
+pub use program::OutputIter;
 pub use program::Style;
+pub use run_future::RunFuture;
 pub use runner::LazyKRunner;
+pub use runner::RunOutcome;
+pub use runner::RunStats;
+pub use runner::RunnerConfig;
+pub use term::Reduction;
+pub use term::Term;
+pub use term::TermHandle;
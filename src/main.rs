@@ -1,44 +1,317 @@
-use clap::Parser;
-use lazyk_rust::LazyKProgram;
-use std::fs;
+mod repl;
+
+use clap::{Parser, Subcommand};
+use lazyk_rust::{LazyKError, LazyKProgram, RunStats, Style};
+use repl::Repl;
+use std::{
+    fs,
+    io::{stdin, stdout},
+    process::exit,
+};
+
+// Exit codes used when the program can't be run to completion.
+// Values follow sysexits.h.
+const EXIT_PARSE_ERROR: i32 = 65;
+const EXIT_NO_INPUT: i32 = 66;
+const EXIT_RUNTIME_ERROR: i32 = 70;
+const EXIT_IO_ERROR: i32 = 74;
+// Used for program exit codes which don't fit in 0..=255.
+const EXIT_CODE_OUT_OF_RANGE: i32 = 255;
 
 /// LazyK interpreter by Dmytro Fedoriaka.
+///
+/// Exits with the exit code returned by the program, or with code 65 on
+/// parsing error, 66 if the program file can't be read and 70 on runtime error.
+/// Program exit codes above 255 are reported as 255. A program may also
+/// return one of the error codes itself.
 #[derive(Parser, Debug)]
-#[command(about)]
+#[command(
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to LazyK program to run.
-    #[arg(index = 1)]
-    program_file: String,
+    #[arg(index = 1, required = true)]
+    program_file: Option<String>,
 
     /// Indicates that PROGRAM_FILE should be interpreted as in-line LazyK code.
     #[arg(short)]
     e: bool,
+
+    /// Prints statistics of the run to the standard error.
+    #[arg(long)]
+    stats: bool,
 }
 
-fn main() {
-    let args = Args::parse();
-    let source = if args.e {
-        args.program_file
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Starts interactive session, where you can define names and evaluate expressions.
+    Repl,
+    /// Converts LazyK program to another style and prints it.
+    Convert {
+        /// Path to LazyK program to convert.
+        program_file: String,
+
+        /// Indicates that PROGRAM_FILE should be interpreted as in-line LazyK code.
+        #[arg(short)]
+        e: bool,
+
+        /// Style of the output.
+        #[arg(short, long, default_value_t = Style::CombCalculus)]
+        style: Style,
+
+        /// Wraps lines longer than given number of characters.
+        #[arg(short, long)]
+        width: Option<usize>,
+
+        /// Writes every shared subexpression once, as a named definition.
+        #[arg(long)]
+        shared: bool,
+
+        /// Replaces subexpressions with shorter equivalent code, in Jot and
+        /// Iota styles.
+        #[arg(long, conflicts_with = "shared")]
+        short: bool,
+
+        /// Simplifies the program before converting it, and reports how much
+        /// smaller it became.
+        #[arg(long)]
+        optimize: bool,
+    },
+    /// Reduces LazyK program to normal form and prints it.
+    Normalize {
+        /// Path to LazyK program to normalize.
+        program_file: String,
+
+        /// Indicates that PROGRAM_FILE should be interpreted as in-line LazyK code.
+        #[arg(short)]
+        e: bool,
+
+        /// Style of the output.
+        #[arg(short, long, default_value_t = Style::CombCalculus)]
+        style: Style,
+
+        /// Maximal number of reductions. Programs without normal form fail
+        /// when it's exhausted.
+        #[arg(short, long, default_value_t = 1000000)]
+        budget: u64,
+    },
+    /// Produces LazyK program that prints given text, as short as it can find.
+    Compress {
+        /// Path to the file with text to print.
+        text_file: String,
+
+        /// Style of the output.
+        #[arg(short, long, default_value_t = Style::CombCalculus)]
+        style: Style,
+
+        /// Wraps lines longer than given number of characters.
+        #[arg(short, long)]
+        width: Option<usize>,
+    },
+}
+
+fn run_repl() {
+    if let Err(err) = Repl::new().run(stdin().lock(), stdout().lock()) {
+        eprintln!("I/O error: {}", err);
+        exit(EXIT_IO_ERROR);
+    }
+}
+
+// Reads and compiles program, exiting on failure.
+fn compile(program_file: String, inline: bool) -> LazyKProgram {
+    let source = if inline {
+        program_file
     } else {
-        match fs::read_to_string(args.program_file) {
+        match fs::read_to_string(program_file) {
             Ok(x) => x,
             Err(err) => {
-                println!("Could not read source: {}", err);
-                return;
+                eprintln!("Could not read source: {}", err);
+                exit(EXIT_NO_INPUT);
             }
         }
     };
 
-    let mut program = match LazyKProgram::compile(&source) {
+    match LazyKProgram::compile(&source) {
         Ok(program) => program,
         Err(err) => {
-            println!("Parsing error: {}", err);
-            return;
+            eprintln!("Parsing error: {}", err);
+            if let LazyKError::Parse(err) = err {
+                eprintln!("{}", err.snippet());
+            }
+            exit(EXIT_PARSE_ERROR);
+        }
+    }
+}
+
+// Breaks lines longer than width. If `keep_names` is set, avoids breaking
+// inside runs of letters and digits, which may be names of definitions.
+fn wrap_lines(source: &str, width: usize, keep_names: bool) -> String {
+    let is_name_char = |ch: char| ch.is_ascii_alphanumeric() || ch == '_';
+    let mut output = String::new();
+    for line in source.lines() {
+        let chars: Vec<char> = line.chars().collect();
+        let mut start = 0;
+        while chars.len() - start > width {
+            let mut end = start + width;
+            while keep_names
+                && end > start
+                && is_name_char(chars[end - 1])
+                && is_name_char(chars[end])
+            {
+                end -= 1;
+            }
+            if end == start {
+                end = start + width;
+            }
+            output.extend(&chars[start..end]);
+            output.push('\n');
+            start = end;
+        }
+        output.extend(&chars[start..]);
+        output.push('\n');
+    }
+    output
+}
+
+fn convert(program: LazyKProgram, style: Style, width: Option<usize>, shared: bool, short: bool) {
+    let source = if shared {
+        program.to_source_shared(style)
+    } else if short {
+        program.to_source_short(style)
+    } else {
+        program.to_source(style)
+    };
+    match source {
+        Ok(source) => match width {
+            Some(width) => print!(
+                "{}",
+                wrap_lines(&source, width.max(1), shared && style != Style::Jot)
+            ),
+            None => println!("{}", source),
+        },
+        Err(err) => {
+            eprintln!("Could not convert program: {}", err);
+            exit(EXIT_RUNTIME_ERROR);
+        }
+    }
+}
+
+fn optimize(program: &mut LazyKProgram) {
+    match program.optimize() {
+        Ok(optimization) => eprintln!(
+            "Optimized program size: {} -> {} combinators.",
+            optimization.size_before, optimization.size_after
+        ),
+        Err(err) => {
+            eprintln!("Could not optimize program: {}", err);
+            exit(EXIT_RUNTIME_ERROR);
+        }
+    }
+}
+
+fn normalize(mut program: LazyKProgram, style: Style, budget: u64) {
+    program.set_step_budget(Some(budget));
+    if let Err(err) = program.normalize() {
+        eprintln!("Could not normalize program: {}", err);
+        exit(EXIT_RUNTIME_ERROR);
+    }
+    convert(program, style, None, false, false);
+}
+
+fn compress(text_file: String, style: Style, width: Option<usize>) {
+    let text = match fs::read(text_file) {
+        Ok(x) => x,
+        Err(err) => {
+            eprintln!("Could not read text: {}", err);
+            exit(EXIT_NO_INPUT);
         }
     };
+    convert(
+        LazyKProgram::make_compressed_printer(&text),
+        style,
+        width,
+        false,
+        true,
+    );
+}
 
-    match program.run_console() {
-        Ok(_) => {}
-        Err(err) => println!("Runtime error: {}", err),
+fn run_program(mut program: LazyKProgram, stats: bool) {
+    let result = program.run_console();
+    if stats {
+        print_stats(program.stats());
+    }
+    match result {
+        Ok(exit_code) => exit(u8::try_from(exit_code).map_or(EXIT_CODE_OUT_OF_RANGE, i32::from)),
+        Err(err) => {
+            eprintln!("Runtime error: {}", err);
+            exit(EXIT_RUNTIME_ERROR);
+        }
+    }
+}
+
+fn print_stats(stats: &RunStats) {
+    let reductions = stats.s_reductions
+        + stats.k_reductions
+        + stats.i_reductions
+        + stats.lazy_read_reductions
+        + stats.inc_reductions;
+    eprintln!(
+        "Reductions: {} (S: {}, K: {}, I: {}, LazyRead: {}, Inc: {})",
+        reductions,
+        stats.s_reductions,
+        stats.k_reductions,
+        stats.i_reductions,
+        stats.lazy_read_reductions,
+        stats.inc_reductions
+    );
+    eprintln!("Allocations: {}", stats.allocations);
+    eprintln!("Peak pool size: {} expressions", stats.peak_pool_size);
+    eprintln!(
+        "Garbage collections: {} ({:.3} s)",
+        stats.gc_cycles,
+        stats.gc_time.as_secs_f64()
+    );
+    eprintln!(
+        "Bytes read: {}, written: {}",
+        stats.bytes_read, stats.bytes_written
+    );
+}
+
+fn main() {
+    let args = Args::parse();
+    match args.command {
+        Some(Command::Repl) => run_repl(),
+        Some(Command::Convert {
+            program_file,
+            e,
+            style,
+            width,
+            shared,
+            short,
+            optimize: optimize_program,
+        }) => {
+            let mut program = compile(program_file, e);
+            if optimize_program {
+                optimize(&mut program);
+            }
+            convert(program, style, width, shared, short)
+        }
+        Some(Command::Normalize {
+            program_file,
+            e,
+            style,
+            budget,
+        }) => normalize(compile(program_file, e), style, budget),
+        Some(Command::Compress {
+            text_file,
+            style,
+            width,
+        }) => compress(text_file, style, width),
+        None => run_program(compile(args.program_file.unwrap(), args.e), args.stats),
     }
 }
@@ -0,0 +1,180 @@
+use crate::{
+    error::{LazyKError, Result},
+    expression::{Expr, ExprId},
+    runner::LazyKRunner,
+};
+use std::collections::HashMap;
+
+/// Size of a program before and after `LazyKProgram::optimize`, counted as
+/// the number of S, K and I combinators written by `to_source`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Optimization {
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+/// Rewrites expression to a smaller equivalent one, using the following rules:
+/// 1. `I x` → `x`;
+/// 2. `K x y` → `x`;
+/// 3. `S K x` → `I`;
+/// 4. `S (K I) x` → `x`;
+/// 5. `S (K x) I` → `x`;
+/// 6. `S (K x) (K y)` → `K (x y)`.
+///
+/// Rules 3-6 hold when both sides are applied to an argument, which is how
+/// Lazy K uses every expression. Every rule makes expression smaller, and
+/// subexpressions are rewritten before the application containing them, so
+/// a single pass leaves no place where a rule can be applied.
+pub(crate) struct Optimizer<'a> {
+    runner: &'a mut LazyKRunner,
+    // Rewritten form of every expression visited so far, to preserve sharing.
+    optimized: HashMap<ExprId, ExprId>,
+}
+
+impl<'a> Optimizer<'a> {
+    pub(crate) fn new(runner: &'a mut LazyKRunner) -> Self {
+        Self {
+            runner,
+            optimized: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn optimize(&mut self, expr_id: ExprId) -> Result<ExprId> {
+        // Programs written in Jot are very deep, so expressions are visited
+        // with explicit stack rather than recursion.
+        let mut stack = vec![expr_id];
+        while let Some(&top) = stack.last() {
+            if self.optimized.contains_key(&top) {
+                stack.pop();
+                continue;
+            }
+            let pending: Vec<ExprId> = args(self.runner, top)?
+                .into_iter()
+                .flatten()
+                .filter(|arg| !self.optimized.contains_key(arg))
+                .collect();
+            if !pending.is_empty() {
+                stack.extend(pending);
+                continue;
+            }
+            let result_id = self.rewrite(top)?;
+            self.optimized.insert(top, result_id);
+            stack.pop();
+        }
+        Ok(self.optimized[&expr_id])
+    }
+
+    // Rewrites expression whose arguments are already optimized.
+    fn rewrite(&mut self, expr_id: ExprId) -> Result<ExprId> {
+        let optimized = &self.optimized;
+        match *self.runner.get_expr(expr_id) {
+            Expr::A(lhs, rhs) => self.app(optimized[&lhs], optimized[&rhs]),
+            Expr::K1(arg) => self.app(self.runner.k, optimized[&arg]),
+            Expr::S1(arg) => self.app(self.runner.s, optimized[&arg]),
+            Expr::S2(arg1, arg2) => {
+                let arg2 = optimized[&arg2];
+                let s_arg1 = self.app(self.runner.s, optimized[&arg1])?;
+                self.app(s_arg1, arg2)
+            }
+            Expr::I1(arg) => Ok(optimized[&arg]),
+            Expr::K => Ok(self.runner.k),
+            Expr::S => Ok(self.runner.s),
+            _ => Ok(self.runner.i),
+        }
+    }
+
+    // Builds application of optimized expressions, rewriting it if possible.
+    fn app(&mut self, lhs: ExprId, rhs: ExprId) -> Result<ExprId> {
+        if self.is_i(lhs) {
+            return Ok(rhs);
+        }
+        if let Some(x) = self.k_arg(lhs) {
+            return Ok(x);
+        }
+        if let Some(s_arg) = self.s_arg(lhs) {
+            if matches!(self.runner.get_expr(s_arg), Expr::K) {
+                return Ok(self.runner.i);
+            }
+            if let Some(x) = self.k_arg(s_arg) {
+                if self.is_i(x) {
+                    return Ok(rhs);
+                }
+                if self.is_i(rhs) {
+                    return Ok(x);
+                }
+                if let Some(y) = self.k_arg(rhs) {
+                    let xy = self.app(x, y)?;
+                    return self.app(self.runner.k, xy);
+                }
+            }
+        }
+        self.runner.partial_apply(lhs, rhs)
+    }
+
+    fn is_i(&self, expr_id: ExprId) -> bool {
+        matches!(self.runner.get_expr(expr_id), Expr::I)
+    }
+
+    // If expression is K applied to one argument, returns that argument.
+    fn k_arg(&self, expr_id: ExprId) -> Option<ExprId> {
+        match *self.runner.get_expr(expr_id) {
+            Expr::K1(arg) => Some(arg),
+            Expr::A(lhs, rhs) if matches!(self.runner.get_expr(lhs), Expr::K) => Some(rhs),
+            _ => None,
+        }
+    }
+
+    // If expression is S applied to one argument, returns that argument.
+    fn s_arg(&self, expr_id: ExprId) -> Option<ExprId> {
+        match *self.runner.get_expr(expr_id) {
+            Expr::S1(arg) => Some(arg),
+            Expr::A(lhs, rhs) if matches!(self.runner.get_expr(lhs), Expr::S) => Some(rhs),
+            _ => None,
+        }
+    }
+}
+
+// Returns arguments of expression that can be written in source code.
+fn args(runner: &LazyKRunner, expr_id: ExprId) -> Result<[Option<ExprId>; 2]> {
+    match *runner.get_expr(expr_id) {
+        Expr::A(arg1, arg2) | Expr::S2(arg1, arg2) => Ok([Some(arg1), Some(arg2)]),
+        Expr::K1(arg) | Expr::S1(arg) | Expr::I1(arg) => Ok([Some(arg), None]),
+        Expr::K | Expr::S | Expr::I => Ok([None, None]),
+        _ => Err(LazyKError::UnprintableExpression),
+    }
+}
+
+/// Counts combinators in expression, with shared subexpressions counted
+/// every time they are used. Expression must be printable.
+pub(crate) fn expr_size(runner: &LazyKRunner, expr_id: ExprId) -> u64 {
+    let mut sizes: HashMap<ExprId, u64> = HashMap::new();
+    let mut stack = vec![expr_id];
+    while let Some(&top) = stack.last() {
+        if sizes.contains_key(&top) {
+            stack.pop();
+            continue;
+        }
+        let args = args(runner, top).expect("Expression is printable.");
+        let pending: Vec<ExprId> = args
+            .into_iter()
+            .flatten()
+            .filter(|arg| !sizes.contains_key(arg))
+            .collect();
+        if !pending.is_empty() {
+            stack.extend(pending);
+            continue;
+        }
+        // Combinators with arguments are written as application of the combinator.
+        let own_size: u64 = match runner.get_expr(top) {
+            Expr::A(_, _) | Expr::I1(_) => 0,
+            _ => 1,
+        };
+        let size = args
+            .into_iter()
+            .flatten()
+            .fold(own_size, |size, arg| size.saturating_add(sizes[&arg]));
+        sizes.insert(top, size);
+        stack.pop();
+    }
+    sizes[&expr_id]
+}
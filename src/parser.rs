@@ -1,33 +1,71 @@
-use crate::{expression::ExprId, runner::LazyKRunner};
-use anyhow::{bail, ensure, Result};
+use crate::{
+    error::{LazyKError, ParseError, Result},
+    expression::ExprId,
+    lambda::{abstract_var, apply, Term},
+    runner::LazyKRunner,
+};
+use std::collections::HashMap;
 
-pub struct Parser {}
+/// Parses LazyK source code in any of the supported styles, which can be mixed.
+///
+/// Besides combinators, source can use lambda abstractions `\x y. body` and
+/// named definitions `name = expr;` preceding the main expression. Names
+/// start with a lowercase letter or underscore. They take precedence over the
+/// lowercase `s`, `k` and `i` combinators, which are still recognized when no
+/// definition or variable with matching name exists (so `sii` is `s i i`).
+pub struct Parser<'a> {
+    source: &'a str,
+    // Byte offset of the next unparsed character.
+    pos: usize,
+    pool: &'a mut LazyKRunner,
+    // Variables bound by enclosing lambda abstractions, innermost last.
+    vars: Vec<String>,
+    definitions: HashMap<String, ExprId>,
+}
+
+fn is_name_start(ch: u8) -> bool {
+    ch.is_ascii_lowercase() || ch == b'_'
+}
+
+fn is_name_char(ch: u8) -> bool {
+    ch.is_ascii_alphanumeric() || ch == b'_'
+}
 
-impl Parser {
-    fn parse_jot(source: &mut &[u8], pool: &mut LazyKRunner) -> ExprId {
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a [u8] {
+        &self.source.as_bytes()[self.pos..]
+    }
+
+    fn error<T>(&self, message: &str, offset: usize) -> Result<T> {
+        let err = ParseError::new(message.to_string(), self.source, offset);
+        Err(LazyKError::Parse(err))
+    }
+
+    fn parse_jot(&mut self) -> Result<ExprId> {
+        let rest = self.rest();
+        let pool = &mut *self.pool;
         let mut e = pool.i;
-        let mut i = 0;
-        while i != source.len() {
-            if source[i] == b'0' {
-                let lhs = pool.partial_apply(e, pool.s);
-                e = pool.partial_apply(lhs, pool.k);
-            } else if source[i] == b'1' {
-                let rhs = pool.partial_apply(pool.k, e);
-                e = pool.partial_apply(pool.s, rhs);
+        for &ch in rest {
+            if ch == b'0' {
+                let lhs = pool.partial_apply(e, pool.s)?;
+                e = pool.partial_apply(lhs, pool.k)?;
+            } else if ch == b'1' {
+                let rhs = pool.partial_apply(pool.k, e)?;
+                e = pool.partial_apply(pool.s, rhs)?;
             }
-            i += 1;
         }
-        *source = &source[i..];
-        e
+        self.pos = self.source.len();
+        Ok(e)
     }
 
-    fn skip_whitespace_and_comments(source: &mut &[u8]) {
+    fn skip_whitespace_and_comments(&mut self) {
         let mut is_comment = false;
-        for i in 0..source.len() {
-            if source[i] >= 128 {
+        let rest = self.rest();
+        for (i, &byte) in rest.iter().enumerate() {
+            if byte >= 128 {
                 continue;
             }
-            let ch = source[i] as char;
+            let ch = byte as char;
             if ch == '#' {
                 is_comment = true;
             }
@@ -37,76 +75,177 @@ impl Parser {
             if ch <= ' ' || is_comment {
                 continue;
             }
-            *source = &source[i..];
+            self.pos += i;
             return;
         }
-        *source = &source[source.len()..];
+        self.pos = self.source.len();
+    }
+
+    // Returns the longest name starting at current position (possibly empty).
+    fn peek_name(&self) -> &'a str {
+        let rest = self.rest();
+        if rest.first().is_none_or(|&ch| !is_name_start(ch)) {
+            return "";
+        }
+        let len = rest.iter().take_while(|&&ch| is_name_char(ch)).count();
+        &self.source[self.pos..self.pos + len]
+    }
+
+    fn lookup(&self, name: &str) -> Option<Term> {
+        if self.vars.iter().any(|var| var == name) {
+            return Some(Term::Var(name.to_string()));
+        }
+        self.definitions.get(name).map(|&e| Term::Const(e))
     }
 
-    fn parse_expr(source: &mut &[u8], i_is_iota: bool, pool: &mut LazyKRunner) -> Result<ExprId> {
-        Self::skip_whitespace_and_comments(source);
-        if source.is_empty() {
-            bail!("Unexpected end of source.")
+    fn parse_expr(&mut self, i_is_iota: bool) -> Result<Term> {
+        self.skip_whitespace_and_comments();
+        let start = self.pos;
+        if start == self.source.len() {
+            return self.error("Unexpected end of source.", start);
         }
-        let ch = source[0] as char;
+        let name = self.peek_name();
+        if let Some(term) = self.lookup(name) {
+            self.pos += name.len();
+            return Ok(term);
+        }
+        let ch = self.rest()[0] as char;
         if ch == '0' || ch == '1' {
-            return Ok(Self::parse_jot(source, pool));
+            return Ok(Term::Const(self.parse_jot()?));
         }
 
-        *source = &source[1..];
+        self.pos += 1;
         match ch {
             '`' | '*' => {
-                let p = Self::parse_expr(source, ch == '*', pool)?;
-                let q = Self::parse_expr(source, ch == '*', pool)?;
-                Ok(pool.partial_apply(p, q))
+                let p = self.parse_expr(ch == '*')?;
+                let q = self.parse_expr(ch == '*')?;
+                apply(self.pool, p, q)
+            }
+            '(' => {
+                let e = self.parse_sequence()?;
+                if self.rest().first() != Some(&b')') {
+                    return self.error("Unmatched open-parenthesis.", start);
+                }
+                self.pos += 1;
+                Ok(e)
             }
-            '(' => Self::parse_manual_close(source, true, pool),
-            ')' => bail!("Mismatched close-parenthesis!"),
-            'k' | 'K' => Ok(pool.k),
-            's' | 'S' => Ok(pool.s),
+            ')' => self.error("Mismatched close-parenthesis!", start),
+            '\\' => self.parse_lambda(),
+            'k' | 'K' => Ok(Term::Const(self.pool.k)),
+            's' | 'S' => Ok(Term::Const(self.pool.s)),
             'i' => {
                 if i_is_iota {
-                    Ok(pool.iota)
+                    Ok(Term::Const(self.pool.iota))
                 } else {
-                    Ok(pool.i)
+                    Ok(Term::Const(self.pool.i))
                 }
             }
-            'I' => Ok(pool.i),
-            _ => bail!("Invalid character: [{}]", ch),
+            'I' => Ok(Term::Const(self.pool.i)),
+            _ => self.error(&format!("Invalid character: [{}]", ch), start),
         }
     }
 
-    fn parse_manual_close(
-        source: &mut &[u8],
-        expected_closing_paren: bool,
-        pool: &mut LazyKRunner,
-    ) -> Result<ExprId> {
-        let mut e: Option<ExprId> = None;
+    // Parses `x y z. body`, following the backslash.
+    fn parse_lambda(&mut self) -> Result<Term> {
+        let mut params = Vec::new();
         loop {
-            Self::skip_whitespace_and_comments(source);
-            if source.is_empty() || source[0] == b')' {
+            self.skip_whitespace_and_comments();
+            let name = self.peek_name();
+            if name.is_empty() {
                 break;
             }
-            let e2 = Self::parse_expr(source, false, pool)?;
+            self.pos += name.len();
+            params.push(name.to_string());
+        }
+        if params.is_empty() || self.rest().first() != Some(&b'.') {
+            return self.error("Expected lambda parameters followed by '.'.", self.pos);
+        }
+        self.pos += 1;
+
+        let num_vars = self.vars.len();
+        self.vars.extend(params.iter().cloned());
+        let body = self.parse_sequence();
+        self.vars.truncate(num_vars);
+        let mut body = body?;
+        for param in params.iter().rev() {
+            body = abstract_var(self.pool, param, body)?;
+        }
+        Ok(body)
+    }
+
+    // Parses sequence of applications, until `)`, `;` or end of source.
+    fn parse_sequence(&mut self) -> Result<Term> {
+        let mut e: Option<Term> = None;
+        loop {
+            self.skip_whitespace_and_comments();
+            if self
+                .rest()
+                .first()
+                .is_none_or(|&ch| ch == b')' || ch == b';')
+            {
+                break;
+            }
+            let e2 = self.parse_expr(false)?;
             e = match e {
-                Some(e) => Some(pool.partial_apply(e, e2)),
+                Some(e) => Some(apply(self.pool, e, e2)?),
                 None => Some(e2),
             }
         }
-        if expected_closing_paren {
-            ensure!(!source.is_empty(), "Premature end of program.");
-            *source = &source[1..];
-        } else {
-            ensure!(source.is_empty(), "Unmatched trailing close-parenthesis.");
-        }
         match e {
             Some(e) => Ok(e),
-            None => Ok(pool.i),
+            None => Ok(Term::Const(self.pool.i)),
+        }
+    }
+
+    // Parses `name = expr;` if it starts at current position.
+    fn parse_definition(&mut self) -> Result<bool> {
+        self.skip_whitespace_and_comments();
+        let start = self.pos;
+        let name = self.peek_name();
+        if name.is_empty() {
+            return Ok(false);
+        }
+        self.pos += name.len();
+        self.skip_whitespace_and_comments();
+        if self.rest().first() != Some(&b'=') {
+            self.pos = start;
+            return Ok(false);
+        }
+        self.pos += 1;
+        let e = self.parse_sequence()?;
+        if self.rest().first() != Some(&b';') {
+            return self.error("Expected ';' after definition.", self.pos);
+        }
+        self.pos += 1;
+        match e {
+            Term::Const(e) => {
+                self.definitions.insert(name.to_string(), e);
+                Ok(true)
+            }
+            _ => unreachable!("Free variables are always bound by enclosing lambdas."),
         }
     }
 
     pub fn parse(source: &str, pool: &mut LazyKRunner) -> Result<ExprId> {
-        let mut b = source.as_bytes();
-        Self::parse_manual_close(&mut b, false, pool)
+        let mut parser = Parser {
+            source,
+            pos: 0,
+            pool,
+            vars: Vec::new(),
+            definitions: HashMap::new(),
+        };
+        while parser.parse_definition()? {}
+        let e = parser.parse_sequence()?;
+        if parser.pos != source.len() {
+            let message = match source.as_bytes()[parser.pos] {
+                b';' => "Unexpected ';'.",
+                _ => "Unmatched trailing close-parenthesis.",
+            };
+            return parser.error(message, parser.pos);
+        }
+        match e {
+            Term::Const(e) => Ok(e),
+            _ => unreachable!("Free variables are always bound by enclosing lambdas."),
+        }
     }
 }
@@ -1,39 +1,88 @@
 use crate::{
+    error::{LazyKError, Result},
     expression::{Expr, ExprId},
     LazyKRunner, Style,
 };
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+};
+
+/// Read-only view of expressions in runner's pool. Pointers temporarily
+/// reversed by unfinished evaluation are seen as they will be when evaluation
+/// completes, so printing doesn't disturb a suspended run.
+pub(crate) struct PoolView<'a> {
+    runner: &'a LazyKRunner,
+    // Actual first arguments of applications on the evaluation spine.
+    links: HashMap<ExprId, ExprId>,
+}
+
+impl<'a> PoolView<'a> {
+    pub(crate) fn new(runner: &'a LazyKRunner) -> Self {
+        let links = match runner.unfinished_eval() {
+            Some((_, _, links)) => links,
+            None => HashMap::new(),
+        };
+        Self { runner, links }
+    }
+
+    pub(crate) fn get(&self, expr_id: ExprId) -> Expr {
+        match *self.runner.get_expr(expr_id) {
+            Expr::A(arg1, arg2) => Expr::A(*self.links.get(&expr_id).unwrap_or(&arg1), arg2),
+            expr => expr,
+        }
+    }
+}
+
+// Pushes token to output, separating it from preceding name if needed.
+// Names end with a digit and combinators never do, so a space is needed only
+// between a name and the following letter or digit.
+fn push_token(output: &mut String, token: &str, names_used: bool) {
+    if names_used
+        && output.ends_with(|ch: char| ch.is_ascii_digit())
+        && token.starts_with(|ch: char| ch.is_ascii_alphanumeric() || ch == '_')
+    {
+        output.push(' ');
+    }
+    output.push_str(token);
+}
 
 /// Prints LazyK program in prefix notation, substituting combinators
 /// and application with custom strings.
 pub(crate) struct GenericPrinter<'a> {
-    runner: &'a LazyKRunner,
-    a: &'static str,
-    k: &'static str,
-    s: &'static str,
-    i: &'static str,
+    pool: PoolView<'a>,
+    // Names of expressions that are printed as references to definitions.
+    names: HashMap<ExprId, String>,
+    pub(crate) a: &'static str,
+    pub(crate) k: &'static str,
+    pub(crate) s: &'static str,
+    pub(crate) i: &'static str,
 }
 
 impl<'a> GenericPrinter<'a> {
     pub(crate) fn new(runner: &'a LazyKRunner, style: Style) -> Self {
+        let pool = PoolView::new(runner);
         match style {
             Style::CombCalculus => panic!("Use CcPrinnter for Combinator-Calculus style."),
             Style::Unlambda => Self {
-                runner,
+                pool,
+                names: HashMap::new(),
                 a: "`",
                 k: "k",
                 s: "s",
                 i: "i",
             },
             Style::Jot => Self {
-                runner,
+                pool,
+                names: HashMap::new(),
                 a: "1",
                 k: "11100",
                 s: "11111000",
                 i: "11111111100000",
             },
             Style::Iota => Self {
-                runner,
-
+                pool,
+                names: HashMap::new(),
                 a: "*",
                 k: "*i*i*ii",
                 s: "*i*i*i*ii",
@@ -42,103 +91,343 @@ impl<'a> GenericPrinter<'a> {
         }
     }
 
-    pub(crate) fn print(&self, expr_id: ExprId) -> String {
+    pub(crate) fn print(&self, expr_id: ExprId) -> Result<String> {
         let mut output = String::new();
-        self.print_expr(expr_id, &mut output);
-        output
+        self.print_expr(expr_id, &mut output)?;
+        Ok(output)
     }
 
-    fn print_expr(&'_ self, expr_id: ExprId, output: &mut String) {
-        match self.runner.get_expr(expr_id) {
-            Expr::A(arg1, arg2) => {
-                output.push_str(self.a);
-                self.print_expr(*arg1, output);
-                self.print_expr(*arg2, output);
-            }
-            Expr::K => output.push_str(self.k),
-            Expr::K1(arg) => {
-                output.push_str(self.a);
-                output.push_str(self.k);
-                self.print_expr(*arg, output);
-            }
-            Expr::S => output.push_str(self.s),
-            Expr::S1(arg) => {
-                output.push_str(self.a);
-                output.push_str(self.s);
-                self.print_expr(*arg, output);
+    fn push(&self, output: &mut String, token: &str) {
+        push_token(output, token, !self.names.is_empty());
+    }
+
+    fn print_expr(&'_ self, expr_id: ExprId, output: &mut String) -> Result<()> {
+        match self.names.get(&expr_id) {
+            Some(name) => {
+                self.push(output, name);
+                Ok(())
             }
-            Expr::S2(arg1, arg2) => {
-                output.push_str(self.a);
-                output.push_str(self.a);
-                output.push_str(self.s);
-                self.print_expr(*arg1, output);
-                self.print_expr(*arg2, output);
+            None => self.print_unnamed(expr_id, output),
+        }
+    }
+
+    fn print_unnamed(&'_ self, expr_id: ExprId, output: &mut String) -> Result<()> {
+        // Lists and programs written in Jot are very deep, so expressions are
+        // visited with explicit stack rather than recursion. Arguments may be
+        // printed as names, unlike the expression itself.
+        let mut stack = vec![(expr_id, false)];
+        while let Some((expr_id, may_be_named)) = stack.pop() {
+            if may_be_named {
+                if let Some(name) = self.names.get(&expr_id) {
+                    self.push(output, name);
+                    continue;
+                }
             }
-            Expr::I => output.push_str(self.i),
-            Expr::I1(arg) => {
-                output.push_str(self.a);
-                output.push_str(self.i);
-                self.print_expr(*arg, output);
+            match self.pool.get(expr_id) {
+                Expr::A(arg1, arg2) => {
+                    self.push(output, self.a);
+                    stack.extend([(arg2, true), (arg1, true)]);
+                }
+                Expr::K => self.push(output, self.k),
+                Expr::K1(arg) => {
+                    self.push(output, self.a);
+                    self.push(output, self.k);
+                    stack.push((arg, true));
+                }
+                Expr::S => self.push(output, self.s),
+                Expr::S1(arg) => {
+                    self.push(output, self.a);
+                    self.push(output, self.s);
+                    stack.push((arg, true));
+                }
+                Expr::S2(arg1, arg2) => {
+                    self.push(output, self.a);
+                    self.push(output, self.a);
+                    self.push(output, self.s);
+                    stack.extend([(arg2, true), (arg1, true)]);
+                }
+                Expr::I => self.push(output, self.i),
+                // Indirection left by reduction, equivalent to its argument.
+                Expr::I1(arg) => stack.push((arg, true)),
+                _ => return Err(LazyKError::UnprintableExpression),
             }
-            _ => panic!("Encountered unprintable expression type."),
         }
+        Ok(())
     }
 }
 
+// Work left to do by `CcPrinter`.
+enum CcTask {
+    // Expression that may be printed as a name, and whether it needs
+    // parentheses if it is not.
+    Print(ExprId, bool),
+    PrintUnnamed(ExprId, bool),
+    CloseParen,
+}
+
 /// Prints expression in combinator-calculus style.
 pub(crate) struct CcPrinter<'a> {
-    runner: &'a LazyKRunner,
+    pool: PoolView<'a>,
+    // Names of expressions that are printed as references to definitions.
+    names: HashMap<ExprId, String>,
 }
 
 impl<'a> CcPrinter<'a> {
     pub(crate) fn new(runner: &'a LazyKRunner) -> Self {
-        Self { runner }
+        Self {
+            pool: PoolView::new(runner),
+            names: HashMap::new(),
+        }
     }
 
-    pub(crate) fn print(&self, expr_id: ExprId) -> String {
+    pub(crate) fn print(&self, expr_id: ExprId) -> Result<String> {
         let mut output = String::new();
-        self.print_expr(expr_id, &mut output, false);
-        output
+        self.print_expr(expr_id, &mut output, false)?;
+        Ok(output)
     }
 
-    fn print_expr(&self, expr_id: ExprId, output: &mut String, need_paren: bool) {
-        match self.runner.get_expr(expr_id) {
-            Expr::S => output.push('S'),
-            Expr::K => output.push('K'),
-            Expr::I => output.push('I'),
-            expr => {
-                if need_paren {
-                    output.push('(');
-                }
-                match *expr {
-                    Expr::A(arg1, arg2) => {
-                        self.print_expr(arg1, output, false);
-                        self.print_expr(arg2, output, true);
-                    }
-                    Expr::K1(arg) => {
-                        output.push('K');
-                        self.print_expr(arg, output, true);
-                    }
+    fn push(&self, output: &mut String, token: &str) {
+        push_token(output, token, !self.names.is_empty());
+    }
 
-                    Expr::S1(arg) => {
-                        output.push('S');
-                        self.print_expr(arg, output, true);
+    fn print_expr(&self, expr_id: ExprId, output: &mut String, need_paren: bool) -> Result<()> {
+        match self.names.get(&expr_id) {
+            Some(name) => {
+                self.push(output, name);
+                Ok(())
+            }
+            None => self.print_unnamed(expr_id, output, need_paren),
+        }
+    }
+
+    fn print_unnamed(&self, expr_id: ExprId, output: &mut String, need_paren: bool) -> Result<()> {
+        // Lists are very deep, so expressions are visited with explicit
+        // stack rather than recursion.
+        let mut stack = vec![CcTask::PrintUnnamed(expr_id, need_paren)];
+        while let Some(task) = stack.pop() {
+            let (expr_id, need_paren) = match task {
+                CcTask::Print(expr_id, need_paren) => match self.names.get(&expr_id) {
+                    Some(name) => {
+                        self.push(output, name);
+                        continue;
                     }
-                    Expr::S2(arg1, arg2) => {
-                        output.push('S');
-                        self.print_expr(arg1, output, true);
-                        self.print_expr(arg2, output, true);
+                    None => (expr_id, need_paren),
+                },
+                CcTask::PrintUnnamed(expr_id, need_paren) => (expr_id, need_paren),
+                CcTask::CloseParen => {
+                    output.push(')');
+                    continue;
+                }
+            };
+            match self.pool.get(expr_id) {
+                Expr::S => self.push(output, "S"),
+                Expr::K => self.push(output, "K"),
+                Expr::I => self.push(output, "I"),
+                // Indirection left by reduction, equivalent to its argument.
+                Expr::I1(arg) => stack.push(CcTask::Print(arg, need_paren)),
+                expr => {
+                    if need_paren {
+                        output.push('(');
+                        stack.push(CcTask::CloseParen);
                     }
-                    Expr::I1(arg) => {
-                        output.push('I');
-                        self.print_expr(arg, output, true);
+                    match expr {
+                        Expr::A(arg1, arg2) => {
+                            stack.extend([CcTask::Print(arg2, true), CcTask::Print(arg1, false)]);
+                        }
+                        Expr::K1(arg) => {
+                            self.push(output, "K");
+                            stack.push(CcTask::Print(arg, true));
+                        }
+                        Expr::S1(arg) => {
+                            self.push(output, "S");
+                            stack.push(CcTask::Print(arg, true));
+                        }
+                        Expr::S2(arg1, arg2) => {
+                            self.push(output, "S");
+                            stack.extend([CcTask::Print(arg2, true), CcTask::Print(arg1, true)]);
+                        }
+                        _ => return Err(LazyKError::UnprintableExpression),
                     }
-                    _ => panic!("Encountered unprintable expression type."),
                 }
-                if need_paren {
-                    output.push(')');
+            }
+        }
+        Ok(())
+    }
+}
+
+// Finds expressions other than S, K and I that are reachable from expr_id
+// in more than one way. They are returned in order in which they can be
+// defined, with every expression after all the expressions it contains.
+fn find_shared(pool: &PoolView, expr_id: ExprId) -> Vec<ExprId> {
+    let children = |expr_id: ExprId| match pool.get(expr_id) {
+        Expr::A(arg1, arg2) | Expr::S2(arg1, arg2) => vec![arg1, arg2],
+        Expr::K1(arg) | Expr::S1(arg) | Expr::I1(arg) => vec![arg],
+        _ => vec![],
+    };
+
+    let mut ref_count: HashMap<ExprId, u32> = HashMap::new();
+    let mut stack = vec![expr_id];
+    while let Some(expr_id) = stack.pop() {
+        let count = ref_count.entry(expr_id).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            stack.extend(children(expr_id));
+        }
+    }
+
+    // Post-order traversal. The flag tells whether children were already visited.
+    let mut shared = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack = vec![(expr_id, false)];
+    while let Some((expr_id, children_visited)) = stack.pop() {
+        if children_visited {
+            if ref_count[&expr_id] > 1 && !children(expr_id).is_empty() {
+                shared.push(expr_id);
+            }
+        } else if visited.insert(expr_id) {
+            stack.push((expr_id, true));
+            stack.extend(children(expr_id).into_iter().rev().map(|id| (id, false)));
+        }
+    }
+    shared
+}
+
+/// Prints expression, emitting every shared subexpression once as a named
+/// definition (`d0 = ...;`), which the parser reads back as the same shared
+/// subexpression.
+pub(crate) fn print_shared(runner: &LazyKRunner, expr_id: ExprId, style: Style) -> Result<String> {
+    if style == Style::Jot {
+        return GenericPrinter::new(runner, style).print(expr_id);
+    }
+    let shared = find_shared(&PoolView::new(runner), expr_id);
+    let names: HashMap<ExprId, String> = shared
+        .iter()
+        .enumerate()
+        .map(|(i, &expr_id)| (expr_id, format!("d{}", i)))
+        .collect();
+
+    let mut output = String::new();
+    if style == Style::CombCalculus {
+        let mut printer = CcPrinter::new(runner);
+        printer.names = names;
+        for expr_id in shared {
+            write!(output, "{} = ", printer.names[&expr_id]).unwrap();
+            printer.print_unnamed(expr_id, &mut output, false)?;
+            output.push_str(";\n");
+        }
+        printer.print_expr(expr_id, &mut output, false)?;
+    } else {
+        let mut printer = GenericPrinter::new(runner, style);
+        printer.names = names;
+        for expr_id in shared {
+            write!(output, "{} = ", printer.names[&expr_id]).unwrap();
+            printer.print_unnamed(expr_id, &mut output)?;
+            output.push_str(";\n");
+        }
+        printer.print_expr(expr_id, &mut output)?;
+    }
+    Ok(output)
+}
+
+/// Prints expression graph in Graphviz DOT format.
+///
+/// Every pool slot reachable from the roots becomes one node, so shared
+/// subexpressions are shown once. Pointers temporarily reversed by unfinished
+/// evaluation are shown as they will be when evaluation completes, and the
+/// node that is about to be reduced is highlighted.
+pub(crate) struct DotPrinter<'a> {
+    runner: &'a LazyKRunner,
+    // Actual first arguments of applications on the evaluation spine.
+    links: HashMap<ExprId, ExprId>,
+    current: ExprId,
+}
+
+impl<'a> DotPrinter<'a> {
+    pub(crate) fn new(runner: &'a LazyKRunner) -> Self {
+        let (current, links) = match runner.unfinished_eval() {
+            Some((_, current, links)) => (current, links),
+            None => (0, HashMap::new()),
+        };
+        Self {
+            runner,
+            links,
+            current,
+        }
+    }
+
+    fn children(&self, expr_id: ExprId) -> Vec<ExprId> {
+        match *self.runner.get_expr(expr_id) {
+            Expr::A(arg1, arg2) => vec![*self.links.get(&expr_id).unwrap_or(&arg1), arg2],
+            Expr::S2(arg1, arg2) => vec![arg1, arg2],
+            Expr::K1(arg) | Expr::S1(arg) | Expr::I1(arg) => vec![arg],
+            _ => vec![],
+        }
+    }
+
+    fn node_attributes(&self, expr_id: ExprId) -> String {
+        let (label, shape) = match self.runner.get_expr(expr_id) {
+            Expr::A(_, _) => ("@".to_string(), "circle"),
+            Expr::K => ("K".to_string(), "plaintext"),
+            Expr::S => ("S".to_string(), "plaintext"),
+            Expr::I => ("I".to_string(), "plaintext"),
+            Expr::K1(_) => ("K1".to_string(), "box"),
+            Expr::S1(_) => ("S1".to_string(), "box"),
+            Expr::S2(_, _) => ("S2".to_string(), "box"),
+            Expr::I1(_) => ("I1".to_string(), "box"),
+            Expr::LazyRead => ("LazyRead".to_string(), "doubleoctagon"),
+            Expr::Inc => ("Inc".to_string(), "octagon"),
+            Expr::Count => ("Count".to_string(), "octagon"),
+            Expr::Num(num) => (format!("Num {}", num), "octagon"),
+            Expr::Free(_) => ("Free".to_string(), "plaintext"),
+        };
+        let highlight = if expr_id == self.current {
+            ", style=filled, fillcolor=yellow"
+        } else {
+            ""
+        };
+        format!("label=\"{}\", shape={}{}", label, shape, highlight)
+    }
+
+    /// Prints subgraph reachable from given roots. Each root is pointed to
+    /// by a label with given name.
+    pub(crate) fn print(&self, roots: &[(&str, ExprId)]) -> String {
+        let mut output = String::from("digraph {\n");
+        let mut visited = HashSet::new();
+        let mut queue = Vec::new();
+        for (name, root) in roots {
+            writeln!(output, "  \"{}\" [shape=plaintext];", name).unwrap();
+            writeln!(output, "  \"{}\" -> n{};", name, root).unwrap();
+            queue.push(*root);
+        }
+        while let Some(expr_id) = queue.pop() {
+            if !visited.insert(expr_id) {
+                continue;
+            }
+            writeln!(
+                output,
+                "  n{} [{}];",
+                expr_id,
+                self.node_attributes(expr_id)
+            )
+            .unwrap();
+            let children = self.children(expr_id);
+            for (i, &child) in children.iter().enumerate() {
+                if children.len() == 2 {
+                    writeln!(
+                        output,
+                        "  n{} -> n{} [label=\"{}\"];",
+                        expr_id,
+                        child,
+                        i + 1
+                    )
+                    .unwrap();
+                } else {
+                    writeln!(output, "  n{} -> n{};", expr_id, child).unwrap();
                 }
+                queue.push(child);
             }
         }
+        output.push_str("}\n");
+        output
     }
 }
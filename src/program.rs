@@ -1,25 +1,78 @@
-use anyhow::Result;
-use std::io::{stdin, stdout, Cursor};
+use clap::ValueEnum;
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{stdin, stdout},
+    iter::FusedIterator,
+    str::FromStr,
+    time::{Duration, Instant},
+};
 
 use crate::{
+    bignum::BigUint,
+    cancellation::CancellationToken,
+    compressor::compressed_printer,
+    encoding::{encode_stream, FromLazyK, ToLazyK},
+    error::{LazyKError, Result},
     expression::{Expr, ExprId},
-    io::{Input, Output},
+    io::{AsyncByteSource, ByteSink, ByteSource, ReadSource, WriteSink},
+    optimizer::{expr_size, Optimization, Optimizer},
     parser::Parser,
-    printer::{CcPrinter, GenericPrinter},
-    runner::LazyKRunner,
+    printer::{print_shared, CcPrinter, DotPrinter, GenericPrinter, PoolView},
+    run_future::RunFuture,
+    runner::{LazyKRunner, OutputStep, RunOutcome, RunStats, RunnerConfig},
+    short_printer::ShortPrinter,
+    term::{Reduction, Term, TermHandle},
 };
 
+// Printers are built in a runner without memory limit, so they always fit.
+static UNLIMITED_MEMORY: &str = "Runner has no memory limit.";
+
+/// Style of LazyK source code.
+///
+/// Names of styles are `comb-calculus` (or `cc`), `unlambda`, `jot` and `iota`.
+///
+/// ```
+/// use lazyk_rust::Style;
+/// assert_eq!("cc".parse::<Style>().unwrap(), Style::CombCalculus);
+/// assert_eq!(Style::CombCalculus.to_string(), "comb-calculus");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
 pub enum Style {
+    #[value(alias = "cc")]
     CombCalculus,
     Unlambda,
     Jot,
     Iota,
 }
 
+impl fmt::Display for Style {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => unreachable!("All styles have names."),
+        }
+    }
+}
+
+impl FromStr for Style {
+    type Err = LazyKError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        <Self as ValueEnum>::from_str(s, true).map_err(|_| LazyKError::UnknownStyle(s.to_string()))
+    }
+}
+
 pub struct LazyKProgram {
     runner: LazyKRunner,
-    root_id: ExprId,
+    // Index of program's expression among runner's roots.
+    root: usize,
     output_limit: Option<usize>,
+    step_budget: Option<u64>,
+    timeout: Option<Duration>,
+    // Output of the run started with `start_vec`.
+    output: Vec<u8>,
+    exit_code: Option<u16>,
 }
 
 /// Compiled LazyK program, ready to be executed.
@@ -33,13 +86,35 @@ impl LazyKProgram {
     /// assert_eq!(program.run_string("abcd").unwrap(), "abcd");
     /// ```
     pub fn compile(source: &str) -> Result<Self> {
-        let mut runner = LazyKRunner::new();
+        Self::compile_with_config(source, RunnerConfig::default())
+    }
+
+    /// Compiles LazyK source to a program that runs with given memory settings.
+    pub fn compile_with_config(source: &str, config: RunnerConfig) -> Result<Self> {
+        let mut runner = LazyKRunner::with_config(config);
         let root_id = Parser::parse(source, &mut runner)?;
-        Ok(Self {
+        Ok(Self::new(runner, root_id))
+    }
+
+    fn new(mut runner: LazyKRunner, root_id: ExprId) -> Self {
+        Self {
+            root: runner.add_root(root_id),
             runner,
-            root_id,
             output_limit: None,
-        })
+            step_budget: None,
+            timeout: None,
+            output: Vec::new(),
+            exit_code: None,
+        }
+    }
+
+    fn root_id(&self) -> ExprId {
+        self.runner.root(self.root)
+    }
+
+    /// Returns memory settings the program runs with.
+    pub fn config(&self) -> &RunnerConfig {
+        self.runner.config()
     }
 
     /// Sets maximal number of cbytes in output, after which program halts.
@@ -48,33 +123,406 @@ impl LazyKProgram {
         self.output_limit = value;
     }
 
+    /// Sets maximal number of reductions performed by a single call to `run_*`,
+    /// `start_vec`, `resume`, `apply`, `call`, `decode_*` or `normalize`, or by
+    /// the future returned by `run_async`. Useful for running programs that may never halt.
+    ///
+    /// When the budget is exhausted, `run_*` methods return an error, while
+    /// `start_vec` and `resume` return `RunOutcome::BudgetExhausted`.
+    pub fn set_step_budget(&mut self, value: Option<u64>) {
+        self.step_budget = value;
+    }
+
+    /// Sets maximal wall-clock time of a single call to any method that
+    /// evaluates the program, or of the whole iteration or future returned
+    /// by `output_iter` and `run_async`. When it passes, the call fails with
+    /// `LazyKError::TimedOut`, and the program can still be used.
+    ///
+    /// ```
+    /// use lazyk_rust::{LazyKError, LazyKProgram};
+    /// use std::time::Duration;
+    /// let mut program = LazyKProgram::compile("SII(SII)").unwrap();
+    /// program.set_timeout(Some(Duration::from_millis(10)));
+    /// assert!(matches!(program.run_vec(vec![]), Err(LazyKError::TimedOut)));
+    /// ```
+    pub fn set_timeout(&mut self, value: Option<Duration>) {
+        self.timeout = value;
+    }
+
+    /// Sets token that stops evaluation when cancelled, failing with
+    /// `LazyKError::Cancelled`. See `CancellationToken`.
+    pub fn set_cancellation_token(&mut self, token: Option<CancellationToken>) {
+        self.runner.set_cancellation_token(token);
+    }
+
+    // Starts measuring time allowed by timeout.
+    pub(crate) fn start_clock(&mut self) {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        self.runner.set_deadline(deadline);
+    }
+
+    // Prepares to evaluate something other than a run.
+    fn start_evaluation(&mut self) {
+        self.runner.collect_garbage_if_due();
+        self.start_clock();
+        self.runner.reset_stats();
+    }
+
+    /// Returns counters of work done by the last run, including its
+    /// continuations with `resume`, or by the last call to `apply`, `call`,
+    /// `decode_*` or `normalize`.
+    pub fn stats(&self) -> &RunStats {
+        self.runner.stats()
+    }
+
     /// Runs program as Vec<u8> -> Vec<u8> function.
+    ///
+    /// Exit code can be retrieved with `exit_code`.
     pub fn run_vec(&mut self, input: Vec<u8>) -> Result<Vec<u8>> {
-        let input = Input::Reader(Box::new(Cursor::new(input)));
-        let mut output = Output::Buffer(Vec::new());
-        self.runner
-            .run(self.root_id, input, &mut output, self.output_limit)?;
-        match output {
-            Output::Buffer(result) => Ok(result),
-            _ => panic!("Unreachable code."),
-        }
+        self.start_vec(input)?.into_exit_code()?;
+        Ok(self.take_output())
     }
 
     /// Runs program as String -> String function.
+    ///
+    /// Exit code can be retrieved with `exit_code`.
     pub fn run_string(&mut self, input: &str) -> Result<String> {
         let result = self.run_vec(input.as_bytes().to_owned())?;
-        String::from_utf8(result).map_err(anyhow::Error::from)
+        Ok(String::from_utf8(result)?)
     }
 
     /// Runs program, reading from standard input and writing to standard output.
-    pub fn run_console(&mut self) -> Result<()> {
-        let input = Input::Reader(Box::new(stdin().lock()));
-        let mut output = Output::Writer(Box::new(stdout().lock()));
+    ///
+    /// Returns program's exit code.
+    pub fn run_console(&mut self) -> Result<u16> {
+        let input = ReadSource::new(stdin().lock());
+        self.run_with(input, &mut WriteSink::new(stdout().lock()))
+    }
+
+    /// Runs program, reading from `input` and writing to `output`.
+    ///
+    /// Errors returned by `input` or `output` stop the program and are
+    /// returned as `LazyKError::Io`. Returns program's exit code.
+    ///
+    /// If step budget is exhausted, the run can be continued with `resume`,
+    /// but its further output is collected for `take_output` instead.
+    ///
+    /// ```
+    /// use lazyk_rust::{LazyKProgram, ReadSource, WriteSink};
+    /// let mut program = LazyKProgram::compile("I").unwrap();
+    /// let mut output = WriteSink::new(Vec::new());
+    /// program.run_with(ReadSource::new(&b"abc"[..]), &mut output).unwrap();
+    /// assert_eq!(output.into_inner(), b"abc");
+    /// ```
+    pub fn run_with<I: ByteSource + 'static>(
+        &mut self,
+        input: I,
+        output: &mut dyn ByteSink,
+    ) -> Result<u16> {
+        self.start_with(Box::new(input))?;
+        self.resume_with(output, self.step_budget)?.into_exit_code()
+    }
+
+    /// Returns future that runs program, reading from `input` and writing to
+    /// `output`. It returns `Poll::Pending` every `yield_every` reductions,
+    /// so other tasks can run, and while it waits for input. Step budget
+    /// limits the total number of reductions.
+    ///
+    /// Only std futures are used, so it can be polled by any executor. The
+    /// future is not `Send`, see `RunFuture`.
+    ///
+    /// ```
+    /// use lazyk_rust::LazyKProgram;
+    /// use std::{collections::VecDeque, future::Future, pin::pin, sync::Arc};
+    /// use std::task::{Context, Poll, Wake, Waker};
+    /// struct NoopWaker;
+    /// impl Wake for NoopWaker {
+    ///     fn wake(self: Arc<Self>) {}
+    /// }
+    /// let mut program = LazyKProgram::compile("I").unwrap();
+    /// let mut output = Vec::new();
+    /// let input = VecDeque::from(b"abc".to_vec());
+    /// let mut future = pin!(program.run_async(input, &mut output, 100));
+    /// let waker = Waker::from(Arc::new(NoopWaker));
+    /// let mut cx = Context::from_waker(&waker);
+    /// let exit_code = loop {
+    ///     if let Poll::Ready(result) = future.as_mut().poll(&mut cx) {
+    ///         break result.unwrap();
+    ///     }
+    /// };
+    /// assert_eq!((exit_code, output), (0, b"abc".to_vec()));
+    /// ```
+    pub fn run_async<'a, I: AsyncByteSource + 'static>(
+        &'a mut self,
+        input: I,
+        output: &'a mut dyn ByteSink,
+        yield_every: u64,
+    ) -> RunFuture<'a> {
+        RunFuture::new(self, input, output, yield_every)
+    }
+
+    /// Starts running program on given input, stopping when it halts or when
+    /// step budget is exhausted. Output can be retrieved with `take_output`.
+    ///
+    /// ```
+    /// use lazyk_rust::{LazyKProgram, RunOutcome};
+    /// let mut program = LazyKProgram::compile("SII(SII)").unwrap();
+    /// program.set_step_budget(Some(1000));
+    /// assert_eq!(program.start_vec(vec![]).unwrap(), RunOutcome::BudgetExhausted);
+    /// assert_eq!(program.resume(1000).unwrap(), RunOutcome::BudgetExhausted);
+    /// ```
+    pub fn start_vec(&mut self, input: Vec<u8>) -> Result<RunOutcome> {
+        self.start_with(Box::new(VecDeque::from(input)))?;
+        self.resume_run(self.step_budget)
+    }
+
+    /// Starts running program on given input, and returns iterator over its
+    /// output, which evaluates the program only as far as needed for the
+    /// next byte. Useful for consuming infinite output.
+    ///
+    /// Iteration ends when the program halts, after which exit code can be
+    /// retrieved with `exit_code`, or after an error. Output limit and step
+    /// budget apply, with the budget limiting reductions for every byte.
+    ///
+    /// ```
+    /// use lazyk_rust::LazyKProgram;
+    /// let mut program = LazyKProgram::compile("I").unwrap();
+    /// let endless_input = || -> std::io::Result<Option<u8>> { Ok(Some(b'a')) };
+    /// let output: Result<Vec<u8>, _> = program.output_iter(endless_input).take(3).collect();
+    /// assert_eq!(output.unwrap(), b"aaa");
+    /// ```
+    pub fn output_iter<I: ByteSource + 'static>(&mut self, input: I) -> OutputIter<'_> {
+        let start_error = self.start_with(Box::new(input)).err();
+        self.start_clock();
+        OutputIter {
+            program: self,
+            start_error,
+            finished: false,
+        }
+    }
+
+    /// Continues run whose step budget was exhausted, allowing it to perform
+    /// at most `budget` more reductions.
+    pub fn resume(&mut self, budget: u64) -> Result<RunOutcome> {
+        self.resume_run(Some(budget))
+    }
+
+    pub(crate) fn start_with(&mut self, input: Box<dyn ByteSource>) -> Result<()> {
+        self.output.clear();
+        self.exit_code = None;
+        self.runner.start(self.root_id(), input)
+    }
+
+    pub(crate) fn step_budget(&self) -> Option<u64> {
+        self.step_budget
+    }
+
+    // Continues run, returning its outcome and the number of reductions performed.
+    pub(crate) fn resume_counted(
+        &mut self,
+        output: &mut dyn ByteSink,
+        budget: u64,
+    ) -> Result<(RunOutcome, u64)> {
+        let (outcome, steps) = self
+            .runner
+            .resume_counted(output, self.output_limit, budget)?;
+        if let RunOutcome::Halted(exit_code) = outcome {
+            self.exit_code = Some(exit_code);
+        }
+        Ok((outcome, steps))
+    }
+
+    fn resume_run(&mut self, budget: Option<u64>) -> Result<RunOutcome> {
+        let mut output = std::mem::take(&mut self.output);
+        let result = self.resume_with(&mut output, budget);
+        self.output = output;
+        result
+    }
+
+    fn resume_with(
+        &mut self,
+        output: &mut dyn ByteSink,
+        budget: Option<u64>,
+    ) -> Result<RunOutcome> {
+        self.exit_code = None;
+        self.start_clock();
+        let outcome = self.runner.resume(output, self.output_limit, budget)?;
+        if let RunOutcome::Halted(exit_code) = outcome {
+            self.exit_code = Some(exit_code);
+        }
+        Ok(outcome)
+    }
+
+    /// Returns exit code of the last run, or None if it didn't halt.
+    ///
+    /// Exit code is the value of the Church numeral that terminates output,
+    /// minus 256. When output limit is reached, exit code is 1.
+    ///
+    /// ```
+    /// use lazyk_rust::LazyKProgram;
+    /// let mut program = LazyKProgram::compile("I").unwrap();
+    /// program.run_string("abc").unwrap();
+    /// assert_eq!(program.exit_code(), Some(0));
+    /// ```
+    pub fn exit_code(&self) -> Option<u16> {
+        self.exit_code
+    }
+
+    /// Returns output produced so far by the run started with `start_vec`.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Treats the program as a Church numeral and returns its value.
+    ///
+    /// Fails with `LazyKError::NumeralOverflow` if the value doesn't fit in `u64`.
+    ///
+    /// ```
+    /// use lazyk_rust::LazyKProgram;
+    /// let mut prog = LazyKProgram::compile("three = \\f x. f (f (f x)); three three").unwrap();
+    /// assert_eq!(prog.decode_u64().unwrap(), 27);
+    /// ```
+    pub fn decode_u64(&mut self) -> Result<u64> {
+        self.start_evaluation();
+        let root_id = self.root_id();
+        self.runner
+            .with_budget(self.step_budget, |runner| runner.church2u64(root_id))
+    }
+
+    /// Treats the program as a Church numeral and returns its value, however
+    /// large. Note that decoding takes time proportional to the value.
+    pub fn decode_big(&mut self) -> Result<BigUint> {
+        self.start_evaluation();
+        let root_id = self.root_id();
+        self.runner
+            .with_budget(self.step_budget, |runner| runner.church2big(root_id))
+    }
+
+    /// Applies the program, as a function, to given value and decodes the
+    /// result. See `ToLazyK` for how values are encoded.
+    ///
+    /// ```
+    /// use lazyk_rust::LazyKProgram;
+    /// let mut swap = LazyKProgram::compile("\\p f. p (\\a b. f b a)").unwrap();
+    /// assert_eq!(swap.call::<_, (bool, u8)>(&(7u8, true)).unwrap(), (true, 7));
+    /// ```
+    pub fn call<A: ToLazyK + ?Sized, R: FromLazyK>(&mut self, arg: &A) -> Result<R> {
+        self.start_evaluation();
+        let arg = arg.to_lazyk(&mut self.runner)?;
+        let e = self.runner.partial_apply(self.root_id(), arg)?;
         self.runner
-            .run(self.root_id, input, &mut output, self.output_limit)?;
+            .with_budget(self.step_budget, |runner| R::from_lazyk(runner, e))
+    }
+
+    /// Applies the program to given arguments and reduces the result.
+    ///
+    /// The result is kept until it's released with `release`, and can be
+    /// printed with `term_to_source` or converted back to a term with `to_term`.
+    ///
+    /// ```
+    /// use lazyk_rust::{LazyKProgram, Reduction, Style, Term};
+    /// let mut flip = LazyKProgram::compile("\\f x y. f y x").unwrap();
+    /// let args = [Term::K, Term::S, Term::source("\\x. x")];
+    /// let result = flip.apply(&args, Reduction::Normal).unwrap();
+    /// assert_eq!(flip.to_term(&result).unwrap(), Term::I);
+    /// let result = flip.apply(&[Term::K], Reduction::Normal).unwrap();
+    /// assert_eq!(flip.term_to_source(&result, Style::CombCalculus).unwrap(), "S(K(SK))K");
+    /// ```
+    pub fn apply(&mut self, args: &[Term], reduction: Reduction) -> Result<TermHandle> {
+        self.start_evaluation();
+        let mut expr_id = self.root_id();
+        for arg in args {
+            let arg = arg.build(&mut self.runner)?;
+            expr_id = self.runner.partial_apply(expr_id, arg)?;
+        }
+        let result_id = match reduction {
+            Reduction::WeakHead => self.runner.whnf(expr_id, self.step_budget)?,
+            Reduction::Normal => self.runner.normalize(expr_id, self.step_budget)?,
+        };
+        Ok(TermHandle {
+            root: self.runner.add_root(result_id),
+        })
+    }
+
+    /// Converts result of `apply` to a term. Note that shared subexpressions
+    /// are copied, so the term may be much larger than the expression.
+    pub fn to_term(&self, handle: &TermHandle) -> Result<Term> {
+        Term::from_expr(&PoolView::new(&self.runner), self.runner.root(handle.root))
+    }
+
+    /// Produces source code for result of `apply`.
+    pub fn term_to_source(&self, handle: &TermHandle, style: Style) -> Result<String> {
+        let expr_id = self.runner.root(handle.root);
+        match style {
+            Style::CombCalculus => CcPrinter::new(&self.runner).print(expr_id),
+            _ => GenericPrinter::new(&self.runner, style).print(expr_id),
+        }
+    }
+
+    /// Releases result of `apply`, so its memory can be reused.
+    pub fn release(&mut self, handle: TermHandle) {
+        self.runner.remove_root(handle.root);
+    }
+
+    /// Reduces the program to normal form, where no reductions are possible
+    /// anywhere inside it. This simplifies combinators, but programs that
+    /// use recursion usually have no normal form, so step budget should be set.
+    ///
+    /// If the budget is exhausted, program is left partially reduced, which
+    /// is still equivalent to the original program.
+    ///
+    /// ```
+    /// use lazyk_rust::{LazyKProgram, Style};
+    /// let mut prog = LazyKProgram::compile("S(K(SKK))(SKK)").unwrap();
+    /// prog.normalize().unwrap();
+    /// assert_eq!(prog.to_source(Style::CombCalculus).unwrap(), "S(K(SKK))(SKK)");
+    /// let mut prog = LazyKProgram::compile("SKK(KS)(SII)").unwrap();
+    /// prog.normalize().unwrap();
+    /// assert_eq!(prog.to_source(Style::CombCalculus).unwrap(), "S");
+    /// ```
+    pub fn normalize(&mut self) -> Result<()> {
+        self.start_evaluation();
+        let result_id = self.runner.normalize(self.root_id(), self.step_budget)?;
+        self.runner.remove_root(self.root);
+        self.root = self.runner.add_root(result_id);
         Ok(())
     }
 
+    /// Rewrites the program to a smaller equivalent one, without running it,
+    /// and returns its size before and after.
+    ///
+    /// The following rules are applied wherever possible:
+    /// * `I x` → `x`;
+    /// * `K x y` → `x`;
+    /// * `S K x` → `I`;
+    /// * `S (K I) x` → `x`;
+    /// * `S (K x) I` → `x`;
+    /// * `S (K x) (K y)` → `K (x y)`.
+    ///
+    /// Unlike `normalize`, this always finishes quickly, but can't simplify
+    /// applications of `S` to three arguments.
+    ///
+    /// ```
+    /// use lazyk_rust::{LazyKProgram, Style};
+    /// let mut prog = LazyKProgram::compile("S(K(SKK))(K(KI))").unwrap();
+    /// let optimization = prog.optimize().unwrap();
+    /// assert_eq!(prog.to_source(Style::CombCalculus).unwrap(), "K(KI)");
+    /// assert_eq!((optimization.size_before, optimization.size_after), (8, 3));
+    /// ```
+    pub fn optimize(&mut self) -> Result<Optimization> {
+        // Suspended run restarts reduction of its current output byte.
+        self.runner.abandon_eval();
+        let root_id = self.root_id();
+        let result_id = Optimizer::new(&mut self.runner).optimize(root_id)?;
+        let optimization = Optimization {
+            size_before: expr_size(&self.runner, root_id),
+            size_after: expr_size(&self.runner, result_id),
+        };
+        self.runner.remove_root(self.root);
+        self.root = self.runner.add_root(result_id);
+        Ok(optimization)
+    }
+
     /// Produces source code for this program.
     ///
     /// There are four supported styles: combinator-calculus, Unlambda, Jot and
@@ -83,18 +531,94 @@ impl LazyKProgram {
     /// ```
     /// use lazyk_rust::{LazyKProgram, Style};
     /// let prog = LazyKProgram::compile("S(SI(K(KI)))(K(KI))").unwrap();
-    /// assert_eq!(prog.to_source(Style::CombCalculus), "S(SI(K(KI)))(K(KI))");
-    /// assert_eq!(prog.to_source(Style::Unlambda), "``s``si`k`ki`k`ki");                                         
-    /// assert_eq!(prog.to_source(Style::Jot), "11111110001111111000111111111000001111001111001111111110000011110011110011111111100000");
-    /// assert_eq!(prog.to_source(Style::Iota), "***i*i*i*ii***i*i*i*ii*ii**i*i*ii**i*i*ii*ii**i*i*ii**i*i*ii*ii");
+    /// assert_eq!(prog.to_source(Style::CombCalculus).unwrap(), "S(SI(K(KI)))(K(KI))");
+    /// assert_eq!(prog.to_source(Style::Unlambda).unwrap(), "``s``si`k`ki`k`ki");
+    /// assert_eq!(prog.to_source(Style::Jot).unwrap(), "11111110001111111000111111111000001111001111001111111110000011110011110011111111100000");
+    /// assert_eq!(prog.to_source(Style::Iota).unwrap(), "***i*i*i*ii***i*i*i*ii*ii**i*i*ii**i*i*ii*ii**i*i*ii**i*i*ii*ii");
     /// ```
-    pub fn to_source(&self, style: Style) -> String {
+    pub fn to_source(&self, style: Style) -> Result<String> {
         match style {
-            Style::CombCalculus => CcPrinter::new(&self.runner).print(self.root_id),
-            _ => GenericPrinter::new(&self.runner, style).print(self.root_id),
+            Style::CombCalculus => CcPrinter::new(&self.runner).print(self.root_id()),
+            _ => GenericPrinter::new(&self.runner, style).print(self.root_id()),
         }
     }
 
+    /// Produces source code for this program, in which every subexpression
+    /// that is used more than once is written once as a named definition.
+    ///
+    /// Unlike `to_source`, size of the output is proportional to the number
+    /// of distinct subexpressions, and compiling it restores the same sharing.
+    /// Jot can't refer to definitions, so in Jot style output is the same as
+    /// for `to_source`.
+    ///
+    /// ```
+    /// use lazyk_rust::{LazyKProgram, Style};
+    /// let prog = LazyKProgram::compile("x = SKK; y = x x; y y").unwrap();
+    /// assert_eq!(prog.to_source(Style::CombCalculus).unwrap(), "SKK(SKK)(SKK(SKK))");
+    /// assert_eq!(
+    ///     prog.to_source_shared(Style::CombCalculus).unwrap(),
+    ///     "d0 = SKK;\nd1 = d0 d0;\nd1 d1"
+    /// );
+    /// ```
+    pub fn to_source_shared(&self, style: Style) -> Result<String> {
+        print_shared(&self.runner, self.root_id(), style)
+    }
+
+    /// Produces source code for this program in Jot or Iota style, in which
+    /// subexpressions are replaced with shorter equivalent code where it
+    /// exists. For other styles, output is the same as for `to_source`.
+    ///
+    /// Short codes are found by trying all Iota programs and Jot bit strings
+    /// up to some length, which takes a moment on first use. The output
+    /// computes the same as the program, but may be built from different
+    /// combinators.
+    ///
+    /// ```
+    /// use lazyk_rust::{LazyKProgram, Style};
+    /// let prog = LazyKProgram::compile("SK").unwrap();
+    /// assert_eq!(prog.to_source(Style::Jot).unwrap(), "11111100011100");
+    /// assert_eq!(prog.to_source_short(Style::Jot).unwrap(), "10");
+    /// assert_eq!(prog.to_source_short(Style::Iota).unwrap(), "*i*ii");
+    /// ```
+    pub fn to_source_short(&self, style: Style) -> Result<String> {
+        match style {
+            Style::Jot | Style::Iota => {
+                ShortPrinter::new(&self.runner, style).print(self.root_id())
+            }
+            _ => self.to_source(style),
+        }
+    }
+
+    /// Produces graph of the program's expressions in Graphviz DOT format.
+    ///
+    /// Shared subexpressions are shown once. Note that the graph is reduced
+    /// in place when the program runs.
+    ///
+    /// ```
+    /// use lazyk_rust::LazyKProgram;
+    /// let prog = LazyKProgram::compile("SKK").unwrap();
+    /// assert!(prog.to_dot().starts_with("digraph {"));
+    /// ```
+    pub fn to_dot(&self) -> String {
+        DotPrinter::new(&self.runner).print(&[("program", self.root_id())])
+    }
+
+    /// Produces graph of the current state of run suspended because step
+    /// budget was exhausted, in Graphviz DOT format.
+    ///
+    /// The graph shows the remaining output list and the expression being
+    /// evaluated, with the node that is about to be reduced highlighted.
+    pub fn state_to_dot(&self) -> String {
+        let mut roots = Vec::new();
+        if let Some(list) = self.runner.run_list() {
+            roots.push(("output", list));
+        }
+        if let Some((eval_root, _, _)) = self.runner.unfinished_eval() {
+            roots.push(("eval", eval_root));
+        }
+        DotPrinter::new(&self.runner).print(&roots)
+    }
+
     /// Produces LazyK program that prints given byte sequence to output.
     ///
     /// ```
@@ -104,16 +628,76 @@ impl LazyKProgram {
     /// ```
     pub fn make_printer(bytes: &[u8]) -> LazyKProgram {
         let mut runner = LazyKRunner::new();
-        let eof = runner.church_char(256);
-        let mut list = runner.pair(eof, runner.k);
-        for i in (0..bytes.len()).rev() {
-            list = runner.pair(runner.church_char(bytes[i] as u16), list);
+        let list = encode_stream(&mut runner, bytes).expect(UNLIMITED_MEMORY);
+        let root_id = runner.new_expr(Expr::K1(list)).expect(UNLIMITED_MEMORY);
+        Self::new(runner, root_id)
+    }
+
+    /// Produces LazyK program that prints given byte sequence to output, and
+    /// is smaller than the one produced by `make_printer` if the sequence
+    /// has repeated parts.
+    ///
+    /// Repeated substrings are defined once and reused, and repetitions of
+    /// the same substring are produced by a loop. Several ways to do this are
+    /// tried, and the one giving the smallest program is kept.
+    ///
+    /// ```
+    /// use lazyk_rust::{LazyKProgram, Style};
+    /// let text = "Hi! ".repeat(100);
+    /// let mut prog = LazyKProgram::make_compressed_printer(text.as_bytes());
+    /// let plain = LazyKProgram::make_printer(text.as_bytes());
+    /// let size = |prog: &LazyKProgram| prog.to_source(Style::CombCalculus).unwrap().len();
+    /// assert!(size(&prog) * 10 < size(&plain));
+    /// assert_eq!(prog.run_string("").unwrap(), text);
+    /// ```
+    pub fn make_compressed_printer(bytes: &[u8]) -> LazyKProgram {
+        let mut runner = LazyKRunner::new();
+        let root_id = compressed_printer(&mut runner, bytes).expect(UNLIMITED_MEMORY);
+        Self::new(runner, root_id)
+    }
+}
+
+/// Iterator over output of a program, created by `LazyKProgram::output_iter`.
+pub struct OutputIter<'a> {
+    program: &'a mut LazyKProgram,
+    // Error of starting the run, returned as the only item.
+    start_error: Option<LazyKError>,
+    finished: bool,
+}
+
+impl Iterator for OutputIter<'_> {
+    type Item = Result<u8>;
+
+    fn next(&mut self) -> Option<Result<u8>> {
+        if self.finished {
+            return None;
         }
-        let root_id = runner.new_expr(Expr::K1(list));
-        Self {
-            runner,
-            root_id,
-            output_limit: None,
+        if let Some(err) = self.start_error.take() {
+            self.finished = true;
+            return Some(Err(err));
+        }
+        let program = &mut *self.program;
+        let step = program
+            .runner
+            .resume_byte(program.output_limit, program.step_budget);
+        match step {
+            Ok(OutputStep::Byte(byte)) => Some(Ok(byte)),
+            Ok(OutputStep::Stopped(outcome)) => {
+                self.finished = true;
+                match outcome.into_exit_code() {
+                    Ok(exit_code) => {
+                        program.exit_code = Some(exit_code);
+                        None
+                    }
+                    Err(err) => Some(Err(err)),
+                }
+            }
+            Err(err) => {
+                self.finished = true;
+                Some(Err(err))
+            }
         }
     }
 }
+
+impl FusedIterator for OutputIter<'_> {}
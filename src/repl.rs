@@ -0,0 +1,157 @@
+use lazyk_rust::{LazyKError, LazyKProgram, Style};
+use std::{
+    io::{self, BufRead, Write},
+    time::Duration,
+};
+
+const HELP: &str = "\
+Enter an expression to run it on the current input and print its output.
+Enter `name = expr` to define a name that can be used in later lines.
+
+Commands:
+  :input TEXT         Sets input for the following runs (\\n is a newline).
+  :show STYLE EXPR    Prints expression reduced to normal form in given style
+                      (comb-calculus or cc, unlambda, jot or iota). If the step
+                      limit is reached first, prints it partially reduced.
+  :defs               Lists definitions.
+  :reset              Forgets all definitions and input.
+  :help               Prints this message.
+  :quit               Exits.";
+
+// Limits for evaluating one line, so that expression that never halts
+// doesn't hang the session.
+const STEP_BUDGET: u64 = 10_000_000;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Interactive session, which keeps definitions and input between lines.
+pub struct Repl {
+    // Source of definitions, one per line.
+    definitions: Vec<String>,
+    input: String,
+}
+
+// Returns name if line has form `name = ...`.
+fn definition_name(line: &str) -> Option<&str> {
+    let (name, _) = line.split_once('=')?;
+    let name = name.trim();
+    let mut chars = name.chars();
+    let is_name = chars
+        .next()
+        .is_some_and(|ch| ch.is_ascii_lowercase() || ch == '_')
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_');
+    is_name.then_some(name)
+}
+
+fn format_error(err: LazyKError) -> String {
+    match err {
+        LazyKError::Parse(err) => format!("Parsing error: {}\n{}", err.message, err.snippet()),
+        err => format!("Error: {}", err),
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self {
+            definitions: Vec::new(),
+            input: String::new(),
+        }
+    }
+
+    /// Reads lines from input and writes responses to output, until end of
+    /// input or `:quit` command.
+    pub fn run(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        writeln!(output, "LazyK REPL. Enter :help for help.")?;
+        write!(output, "> ")?;
+        output.flush()?;
+        for line in input.lines() {
+            match self.execute(&line?) {
+                Some(response) if response.is_empty() => {}
+                Some(response) => writeln!(output, "{}", response)?,
+                None => return Ok(()),
+            }
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+        writeln!(output)
+    }
+
+    // Executes one line. Returns text to print, or None if session should end.
+    fn execute(&mut self, line: &str) -> Option<String> {
+        let line = line.trim();
+        if let Some(command) = line.strip_prefix(':') {
+            let (command, arg) = command.split_once(' ').unwrap_or((command, ""));
+            return self.execute_command(command, arg.trim());
+        }
+        if line.is_empty() {
+            return Some(String::new());
+        }
+        if let Some(name) = definition_name(line) {
+            let definition = format!("{};", line.trim_end_matches(';'));
+            // Check that definition is valid before keeping it.
+            return Some(match self.compile(&format!("{}\nI", definition)) {
+                Ok(_) => {
+                    self.definitions.push(definition);
+                    format!("Defined {}.", name)
+                }
+                Err(err) => format_error(err),
+            });
+        }
+        Some(match self.evaluate(line) {
+            Ok(output) => output,
+            Err(err) => format_error(err),
+        })
+    }
+
+    fn execute_command(&mut self, command: &str, arg: &str) -> Option<String> {
+        Some(match command {
+            "input" => {
+                self.input = arg.replace("\\n", "\n");
+                String::new()
+            }
+            "show" => {
+                let (style, expr) = arg.split_once(' ').unwrap_or((arg, ""));
+                let source = style.parse::<Style>().and_then(|style| {
+                    let mut program = self.compile(expr)?;
+                    match program.normalize() {
+                        // Partially reduced expression is still equivalent.
+                        Ok(()) | Err(LazyKError::BudgetExhausted | LazyKError::TimedOut) => {}
+                        Err(err) => return Err(err),
+                    }
+                    program.to_source(style)
+                });
+                match source {
+                    Ok(source) => source,
+                    Err(err) => format_error(err),
+                }
+            }
+            "defs" => self.definitions.join("\n"),
+            "reset" => {
+                *self = Self::new();
+                String::new()
+            }
+            "help" => HELP.to_string(),
+            "quit" | "q" => return None,
+            _ => format!("Unknown command: {}. Enter :help for help.", command),
+        })
+    }
+
+    // Compiles expression in context of all definitions. Definitions are on
+    // separate lines preceding the expression.
+    fn compile(&self, expr: &str) -> Result<LazyKProgram, LazyKError> {
+        let mut source = self.definitions.join("\n");
+        source.push('\n');
+        source.push_str(expr);
+        let mut program = LazyKProgram::compile(&source)?;
+        program.set_step_budget(Some(STEP_BUDGET));
+        program.set_timeout(Some(TIMEOUT));
+        Ok(program)
+    }
+
+    fn evaluate(&self, expr: &str) -> Result<String, LazyKError> {
+        let mut program = self.compile(expr)?;
+        let output = program.run_vec(self.input.as_bytes().to_vec())?;
+        let output = String::from_utf8_lossy(&output);
+        // Response is followed by a newline anyway.
+        Ok(output.strip_suffix('\n').unwrap_or(&output).to_string())
+    }
+}
@@ -0,0 +1,113 @@
+use crate::{
+    error::{LazyKError, Result},
+    io::{AsyncByteSource, ByteSink, ByteSource},
+    program::LazyKProgram,
+    runner::RunOutcome,
+};
+use std::{
+    cell::RefCell,
+    future::Future,
+    io::{self, ErrorKind},
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+/// Lets the runner read from asynchronous source. When the next byte is not
+/// available yet, reading fails with `WouldBlock`, which suspends the run.
+struct AsyncInput<I> {
+    source: I,
+    // Waker of the task that polls the run, updated on every poll.
+    waker: Rc<RefCell<Option<Waker>>>,
+}
+
+impl<I: AsyncByteSource> ByteSource for AsyncInput<I> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let waker = self.waker.borrow();
+        let waker = waker
+            .as_ref()
+            .expect("Input is read only while the future is polled.");
+        match self.source.poll_read_byte(&mut Context::from_waker(waker)) {
+            Poll::Ready(result) => result,
+            Poll::Pending => Err(ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+/// Future running a program, created by `LazyKProgram::run_async`.
+///
+/// Resolves to program's exit code.
+///
+/// The future is not `Send`, as neither is the program, whose input and
+/// output don't have to be. On a multithreaded executor, it has to run on
+/// the thread that created it, e.g. with `spawn_local` in tokio's `LocalSet`.
+pub struct RunFuture<'a> {
+    program: &'a mut LazyKProgram,
+    output: &'a mut dyn ByteSink,
+    waker: Rc<RefCell<Option<Waker>>>,
+    // Error of starting the run, returned by the first poll.
+    start_error: Option<LazyKError>,
+    yield_every: u64,
+    // Reductions left of program's step budget.
+    steps_left: Option<u64>,
+}
+
+impl<'a> RunFuture<'a> {
+    pub(crate) fn new<I: AsyncByteSource + 'static>(
+        program: &'a mut LazyKProgram,
+        input: I,
+        output: &'a mut dyn ByteSink,
+        yield_every: u64,
+    ) -> Self {
+        let waker = Rc::new(RefCell::new(None));
+        let input = AsyncInput {
+            source: input,
+            waker: waker.clone(),
+        };
+        let steps_left = program.step_budget();
+        let start_error = program.start_with(Box::new(input)).err();
+        program.start_clock();
+        Self {
+            program,
+            output,
+            waker,
+            start_error,
+            yield_every: yield_every.max(1),
+            steps_left,
+        }
+    }
+}
+
+impl Future for RunFuture<'_> {
+    type Output = Result<u16>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<u16>> {
+        let this = self.get_mut();
+        if let Some(err) = this.start_error.take() {
+            return Poll::Ready(Err(err));
+        }
+        *this.waker.borrow_mut() = Some(cx.waker().clone());
+        let budget = match this.steps_left {
+            Some(0) => return Poll::Ready(Err(LazyKError::BudgetExhausted)),
+            Some(steps_left) => steps_left.min(this.yield_every),
+            None => this.yield_every,
+        };
+        let (outcome, steps) = match this.program.resume_counted(this.output, budget) {
+            Ok(result) => result,
+            Err(err) => return Poll::Ready(Err(err)),
+        };
+        if let Some(steps_left) = &mut this.steps_left {
+            *steps_left -= steps;
+        }
+        match outcome {
+            RunOutcome::Halted(exit_code) => Poll::Ready(Ok(exit_code)),
+            // Let other tasks run, and continue as soon as possible.
+            RunOutcome::BudgetExhausted => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            // Input source will wake the task when input is available.
+            RunOutcome::WaitingForInput => Poll::Pending,
+        }
+    }
+}
@@ -1,12 +1,16 @@
 use crate::{
+    bignum::BigUint,
+    cancellation::CancellationToken,
+    error::{LazyKError, Result},
     expression::{Expr, ExprId},
-    io::{Input, Output},
-    util::{num_repr, NumRepr},
+    io::{ByteSink, ByteSource},
+    util::{num_repr, BitSet, NumRepr},
 };
-use anyhow::{bail, Result};
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, HashSet, VecDeque},
+    io,
     mem::{size_of, swap},
+    time::{Duration, Instant},
 };
 
 pub struct LazyKRunner {
@@ -19,26 +23,190 @@ pub struct LazyKRunner {
     pub inc: ExprId,
     pub zero: ExprId,
     pub iota: ExprId,
-    input: Input,
+    count_expr: ExprId,
+    input: Box<dyn ByteSource>,
+    config: RunnerConfig,
 
+    // First slot of the list of Free slots, linked through their arguments.
     // If zero, there are no Free slots.
-    // If not zero, id of next potential slot.
-    gc_free_ptr: usize,
+    gc_free_head: ExprId,
     gc_queue: VecDeque<ExprId>,
+    // Expressions found to be needed by the last collection.
+    gc_marks: BitSet,
+    // Number of slots that are not Free.
+    used_exprs: usize,
+    // Garbage collector runs when number of used slots exceeds this.
+    gc_limit: usize,
+    // Number of garbage collections so far.
+    gc_cycles: u64,
+    // Counters of work done since the last run was started.
+    stats: RunStats,
+    // Allocating more expressions than this is an error.
+    max_exprs: usize,
+    // Expressions kept alive by garbage collector (see `add_root`).
+    roots: Vec<ExprId>,
+    // Indices of removed roots, which can be reused.
+    free_roots: Vec<usize>,
+    // Expressions kept alive temporarily (see `push_root`).
+    temp_roots: Vec<ExprId>,
+
+    // Number of reductions left before evaluation is suspended (None means unlimited).
+    steps_left: Option<u64>,
+    // Evaluation fails when this token is cancelled, or when deadline passes.
+    cancellation_token: Option<CancellationToken>,
+    deadline: Option<Instant>,
+    // Number of reductions left before the token and deadline are checked.
+    steps_until_check: u32,
+    // State of unfinished evaluation (see `continue_eval`). Zero if there is none.
+    eval_cur: ExprId,
+    eval_prev: ExprId,
+    // Applications of Inc waiting for their argument to be evaluated, with their parents.
+    eval_frames: Vec<(ExprId, ExprId)>,
+    // State of unfinished run, if any.
+    run_state: Option<RunState>,
+    // Set when evaluation is suspended because input is not available yet.
+    waiting_for_input: bool,
+    // Number of reductions of Count, and its maximal allowed value.
+    count: BigUint,
+    count_limit: Option<u64>,
+}
+
+struct RunState {
+    // Remaining output list.
+    list: ExprId,
+    output_size: usize,
+}
+
+/// Result of running a program with a limited reduction budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// Program halted with given exit code.
+    Halted(u16),
+    /// Reduction budget was exhausted before program halted.
+    /// The run can be continued by calling `resume`.
+    BudgetExhausted,
+    /// Input source returned an error of kind `WouldBlock`. The run can be
+    /// continued by calling `resume` when more input is available.
+    WaitingForInput,
+}
+
+impl RunOutcome {
+    // Exit code of a run that was expected to finish.
+    pub(crate) fn into_exit_code(self) -> Result<u16> {
+        match self {
+            RunOutcome::Halted(exit_code) => Ok(exit_code),
+            RunOutcome::BudgetExhausted => Err(LazyKError::BudgetExhausted),
+            RunOutcome::WaitingForInput => Err(io::Error::from(io::ErrorKind::WouldBlock).into()),
+        }
+    }
+}
+
+/// Result of `LazyKRunner::resume_byte`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OutputStep {
+    /// Program wrote a byte, and the run can be continued.
+    Byte(u8),
+    Stopped(RunOutcome),
+}
+
+/// Counters of work done by a run, see `LazyKProgram::stats`.
+///
+/// Reductions are counted per combinator that was applied to all its
+/// arguments: `S x y z`, `K x y`, `I x`, reading of input, and `Inc`, which
+/// is used to decode output bytes.
+///
+/// ```
+/// use lazyk_rust::LazyKProgram;
+/// let mut program = LazyKProgram::compile("I").unwrap();
+/// program.run_string("abc").unwrap();
+/// assert_eq!(program.stats().bytes_read, 3);
+/// assert_eq!(program.stats().bytes_written, 3);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunStats {
+    pub s_reductions: u64,
+    pub k_reductions: u64,
+    pub i_reductions: u64,
+    pub lazy_read_reductions: u64,
+    pub inc_reductions: u64,
+    /// Number of expressions created.
+    pub allocations: u64,
+    pub gc_cycles: u64,
+    /// Maximal number of expressions in use at the same time.
+    pub peak_pool_size: usize,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Time spent in garbage collection.
+    pub gc_time: Duration,
 }
 
-// Garbade collector will try to keep memory usage below this number.
-static GC_LIMIT_BYTES: usize = 256 * 1024 * 1024;
-static GC_LIMIT_EXPR: usize = GC_LIMIT_BYTES / size_of::<Expr>();
+/// Memory settings of a runner.
+///
+/// Memory sizes refer to the pool of expressions, which is where almost all
+/// memory of a running program goes.
+///
+/// ```
+/// use lazyk_rust::{LazyKProgram, RunnerConfig};
+/// let config = RunnerConfig {
+///     memory_limit_bytes: Some(1 << 20),
+///     ..RunnerConfig::default()
+/// };
+/// let mut program = LazyKProgram::compile_with_config("S(SII)I(S(SII)I)", config).unwrap();
+/// assert!(program.run_string("").is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RunnerConfig {
+    /// Number of expressions to allocate memory for upfront.
+    pub initial_capacity: usize,
+    /// Garbage collector runs when expressions in use take more memory than
+    /// this.
+    pub gc_threshold_bytes: usize,
+    /// Hard limit on memory used by expressions. When it is exceeded, running
+    /// program, or building expressions such as encoded arguments, fails with
+    /// `LazyKError::OutOfMemory`.
+    pub memory_limit_bytes: Option<usize>,
+    /// After garbage collection, threshold is raised to this many times the
+    /// memory taken by expressions that are still in use, so that programs with
+    /// large live data don't spend all their time collecting garbage.
+    pub gc_growth_factor: f64,
+    /// Whether garbage collector moves expressions in use to the beginning
+    /// of the pool and releases memory taken by the rest. Otherwise freed
+    /// slots are only reused for new expressions.
+    pub compacting_gc: bool,
+}
+
+impl Default for RunnerConfig {
+    fn default() -> Self {
+        Self {
+            initial_capacity: 1000000,
+            gc_threshold_bytes: 256 * 1024 * 1024,
+            memory_limit_bytes: None,
+            gc_growth_factor: 2.0,
+            compacting_gc: false,
+        }
+    }
+}
+
+static EXPR_SIZE: usize = size_of::<Expr>();
 // Number of expressions at the beginning that are never garbage-collected.
-static PREAMBLE_LENGTH: usize = 448;
+static PREAMBLE_LENGTH: usize = 449;
 // This Church number is used to mark end of input/output.
 static EOF_MARKER: u16 = 256;
 
+// Number of reductions between checks of cancellation token and deadline.
+static CANCELLATION_CHECK_INTERVAL: u32 = 1024;
+
 impl LazyKRunner {
     pub fn new() -> Self {
-        let mut pool: Vec<Expr> = Vec::with_capacity(1000000);
-        pool.push(Expr::Free);
+        Self::with_config(RunnerConfig::default())
+    }
+
+    pub fn with_config(config: RunnerConfig) -> Self {
+        let max_exprs = config
+            .memory_limit_bytes
+            .map_or(usize::MAX, |limit| limit / EXPR_SIZE);
+        let mut pool: Vec<Expr> = Vec::with_capacity(config.initial_capacity.min(max_exprs));
+        pool.push(Expr::Free(0));
         let mut n = |expr: Expr| {
             pool.push(expr);
             (pool.len() - 1) as ExprId
@@ -55,6 +223,7 @@ impl LazyKRunner {
         let iota = n(Expr::S2(siks, kk));
         let inc = n(Expr::Inc);
         let zero = n(Expr::Num(0));
+        let count_expr = n(Expr::Count);
 
         let mut church_chars = vec![ki, i];
         for i in 2..=EOF_MARKER {
@@ -66,7 +235,16 @@ impl LazyKRunner {
             church_chars.push(n(church_expr));
         }
         assert!(pool.len() <= PREAMBLE_LENGTH);
-        Self {
+        let mut runner = Self {
+            used_exprs: pool.len(),
+            gc_limit: 0,
+            gc_cycles: 0,
+            stats: RunStats::default(),
+            max_exprs,
+            roots: Vec::new(),
+            free_roots: Vec::new(),
+            temp_roots: Vec::new(),
+            config,
             e: pool,
             church_chars,
             s,
@@ -76,53 +254,197 @@ impl LazyKRunner {
             inc,
             zero,
             iota,
-            input: Input::Null,
-            gc_free_ptr: 0,
+            count_expr,
+            input: Box::new(io::empty()),
+            gc_free_head: 0,
             gc_queue: VecDeque::new(),
+            gc_marks: BitSet::default(),
+            steps_left: None,
+            cancellation_token: None,
+            deadline: None,
+            steps_until_check: 0,
+            eval_cur: 0,
+            eval_prev: 0,
+            eval_frames: Vec::new(),
+            run_state: None,
+            waiting_for_input: false,
+            count: BigUint::default(),
+            count_limit: None,
+        };
+        runner.update_gc_limit();
+        runner
+    }
+
+    pub fn config(&self) -> &RunnerConfig {
+        &self.config
+    }
+
+    /// Sets token that makes evaluation fail with `LazyKError::Cancelled`
+    /// when cancelled. It's checked every few reductions.
+    pub fn set_cancellation_token(&mut self, token: Option<CancellationToken>) {
+        self.cancellation_token = token;
+    }
+
+    /// Returns counters of work done since the last run was started, or since
+    /// `reset_stats` was called.
+    pub fn stats(&self) -> &RunStats {
+        &self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = RunStats {
+            peak_pool_size: self.used_exprs,
+            ..RunStats::default()
+        };
+    }
+
+    /// Sets time after which evaluation fails with `LazyKError::TimedOut`.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+        self.steps_until_check = 0;
+    }
+
+    /// Registers expression that must not be freed by garbage collector.
+    /// Returns index by which its id can be retrieved with `root`, as
+    /// compacting garbage collector may move it.
+    pub fn add_root(&mut self, expr_id: ExprId) -> usize {
+        match self.free_roots.pop() {
+            Some(index) => {
+                self.roots[index] = expr_id;
+                index
+            }
+            None => {
+                self.roots.push(expr_id);
+                self.roots.len() - 1
+            }
         }
     }
 
+    /// Allows expression registered with `add_root` to be freed. Its index
+    /// may be reused by later calls to `add_root`.
+    pub fn remove_root(&mut self, index: usize) {
+        // Zero is a permanent Free slot, so it's harmless to keep.
+        self.roots[index] = 0;
+        self.free_roots.push(index);
+    }
+
+    /// Returns current id of expression registered with `add_root`.
+    pub fn root(&self, index: usize) -> ExprId {
+        self.roots[index]
+    }
+
+    /// Keeps expression alive until matching `pop_root`, e.g. while other
+    /// expressions are evaluated.
+    pub fn push_root(&mut self, expr_id: ExprId) {
+        self.temp_roots.push(expr_id);
+    }
+
+    /// Removes expression added by the last `push_root`, returning its
+    /// current id.
+    pub fn pop_root(&mut self) -> ExprId {
+        self.temp_roots
+            .pop()
+            .expect("There are no temporary roots.")
+    }
+
     #[inline(always)]
-    fn new_expr_push(&mut self, expr: Expr) -> ExprId {
+    fn new_expr_push(&mut self, expr: Expr) -> Result<ExprId> {
+        if self.e.len() == self.e.capacity() {
+            self.grow_pool()?;
+        }
         let ans = self.e.len() as ExprId;
         self.e.push(expr);
-        ans
+        Ok(ans)
     }
 
-    pub(crate) fn new_expr(&mut self, expr: Expr) -> ExprId {
-        if self.gc_free_ptr == 0 {
-            return self.new_expr_push(expr);
+    // Doubles capacity of the pool, but not beyond the memory limit.
+    #[cold]
+    fn grow_pool(&mut self) -> Result<()> {
+        let room = self.max_exprs.saturating_sub(self.e.len());
+        if room == 0 {
+            return Err(LazyKError::OutOfMemory);
         }
-        // Try use next free slot.
-        for i in self.gc_free_ptr..self.e.len() {
-            if let Expr::Free = self.e[i] {
-                self.e[i] = expr;
-                self.gc_free_ptr = i + 1;
-                return i as ExprId;
+        self.e.reserve_exact(self.e.capacity().min(room).max(1));
+        Ok(())
+    }
+
+    // Fails when memory limit is reached, even outside of evaluation, where
+    // garbage collector doesn't run.
+    pub(crate) fn new_expr(&mut self, expr: Expr) -> Result<ExprId> {
+        let ans = if self.gc_free_head == 0 {
+            self.new_expr_push(expr)?
+        } else {
+            // Take first free slot.
+            let ans = self.gc_free_head;
+            match self.e[ans as usize] {
+                Expr::Free(next) => self.gc_free_head = next,
+                _ => panic!("Free list points to a used slot!"),
             }
+            self.e[ans as usize] = expr;
+            ans
+        };
+        self.used_exprs += 1;
+        self.stats.allocations += 1;
+        self.stats.peak_pool_size = self.stats.peak_pool_size.max(self.used_exprs);
+        Ok(ans)
+    }
+
+    // Frees all expressions not reachable from roots: preamble, registered
+    // roots, and state of unfinished run and evaluation. If expressions are
+    // moved, ids in roots are updated.
+    fn garbage_collect(&mut self) {
+        let start = Instant::now();
+        self.gc_cycles += 1;
+        self.stats.gc_cycles += 1;
+        self.mark();
+        if self.config.compacting_gc {
+            self.compact();
+        } else {
+            self.sweep();
         }
-        // Reached end of allocated pool, push.
-        self.gc_free_ptr = 0;
-        self.new_expr_push(expr)
+        self.update_gc_limit();
+        self.stats.gc_time += start.elapsed();
     }
 
-    // Frees all expressions not reachable from expr_id.
-    fn garbage_collect(&mut self, expr_id: ExprId) {
-        let fp = self.gc_free_ptr;
+    /// Collects garbage if enough of it may have accumulated since the last
+    /// collection. Expressions not reachable from roots must not be in use.
+    pub(crate) fn collect_garbage_if_due(&mut self) {
+        if self.used_exprs > self.gc_limit {
+            self.garbage_collect();
+        }
+    }
+
+    // Next collection happens when number of used slots grows enough, but
+    // always before memory limit is reached.
+    fn update_gc_limit(&mut self) {
+        let grown_limit = (self.used_exprs as f64 * self.config.gc_growth_factor) as usize;
+        self.gc_limit = (self.config.gc_threshold_bytes / EXPR_SIZE)
+            .max(grown_limit)
+            .min(self.max_exprs.saturating_sub(1));
+    }
+
+    // Marks all expressions that are still needed in `gc_marks`.
+    fn mark(&mut self) {
         let n = self.e.len();
-        let need_gc = (fp == 0 && n > GC_LIMIT_EXPR) || (fp > GC_LIMIT_EXPR);
-        if !need_gc {
-            return;
+        // Preamble is never freed, but expressions in it may have been
+        // reduced to point to expressions outside of it.
+        self.gc_queue.extend(0..PREAMBLE_LENGTH.min(n) as ExprId);
+        self.gc_queue.extend(&self.roots);
+        self.gc_queue.extend(&self.temp_roots);
+        self.gc_queue.extend(self.run_list());
+        // Pointers reversed by unfinished evaluation lead from `eval_prev`
+        // and frames' parents back to the root of evaluated expression.
+        self.gc_queue.extend([self.eval_cur, self.eval_prev]);
+        for &(inc_app, parent) in &self.eval_frames {
+            self.gc_queue.extend([inc_app, parent]);
         }
 
         // BFS.
-        let mut needed: Vec<bool> = vec![false; n];
-        self.gc_queue.push_back(expr_id);
+        self.gc_marks.reset(n);
         while let Some(next_id) = self.gc_queue.pop_front() {
-            if needed[next_id as usize] {
+            if !self.gc_marks.insert(next_id as usize) {
                 continue;
             }
-            needed[next_id as usize] = true;
             match &self.e[next_id as usize] {
                 Expr::A(arg1, arg2) | Expr::S2(arg1, arg2) => {
                     self.gc_queue.push_back(*arg1);
@@ -132,66 +454,164 @@ impl LazyKRunner {
                 _ => {}
             }
         }
+    }
+
+    fn is_needed(&self, expr_id: usize) -> bool {
+        expr_id < PREAMBLE_LENGTH || self.gc_marks.contains(expr_id)
+    }
+
+    // Links expressions that are not needed into the free list.
+    fn sweep(&mut self) {
+        let n = self.e.len();
+        // Link free slots in reverse, so they are reused in order of their ids.
+        let mut head = 0;
+        let mut freed = 0;
+        for i in (0..n).rev() {
+            if !self.is_needed(i) {
+                self.e[i] = Expr::Free(head);
+                head = i as ExprId;
+                freed += 1;
+            }
+        }
+        self.gc_free_head = head;
+        self.used_exprs = n - freed;
+    }
+
+    // Slides needed expressions to the beginning of the pool, keeping their
+    // order, and truncates the pool.
+    fn compact(&mut self) {
+        let n = self.e.len();
+        // New id of every needed expression. Preamble stays in place.
+        let mut forward: Vec<ExprId> = vec![0; n];
+        let mut next = 0;
+        for (i, new_id) in forward.iter_mut().enumerate() {
+            if self.is_needed(i) {
+                *new_id = next;
+                next += 1;
+            }
+        }
+        let relocate = |id: &mut ExprId| *id = forward[*id as usize];
         #[allow(clippy::needless_range_loop)]
-        for i in PREAMBLE_LENGTH..n {
-            if !needed[i] {
-                self.e[i] = Expr::Free;
+        for i in 0..n {
+            if !self.is_needed(i) {
+                continue;
+            }
+            match &mut self.e[i] {
+                Expr::A(arg1, arg2) | Expr::S2(arg1, arg2) => {
+                    relocate(arg1);
+                    relocate(arg2);
+                }
+                Expr::K1(arg1) | Expr::S1(arg1) | Expr::I1(arg1) => relocate(arg1),
+                _ => {}
             }
+            // Slot at the new position is either free or already moved.
+            self.e.swap(i, forward[i] as usize);
         }
-        self.gc_free_ptr = PREAMBLE_LENGTH;
+        self.roots.iter_mut().for_each(relocate);
+        self.temp_roots.iter_mut().for_each(relocate);
+        if let Some(state) = &mut self.run_state {
+            relocate(&mut state.list);
+        }
+        relocate(&mut self.eval_cur);
+        relocate(&mut self.eval_prev);
+        for (inc_app, parent) in &mut self.eval_frames {
+            relocate(inc_app);
+            relocate(parent);
+        }
+        self.e.truncate(next as usize);
+        self.e.shrink_to(self.config.initial_capacity);
+        self.gc_free_head = 0;
+        self.used_exprs = self.e.len();
     }
 
-    fn partial_eval_primitive_application(&mut self, expr_id: ExprId) {
+    fn partial_eval_primitive_application(&mut self, expr_id: ExprId) -> Result<()> {
+        // Single reduction allocates only a few expressions, so checking here
+        // keeps memory usage close to the limit.
+        if self.used_exprs >= self.max_exprs {
+            return Err(LazyKError::OutOfMemory);
+        }
+        if let Some(steps_left) = &mut self.steps_left {
+            *steps_left -= 1;
+        }
         match self.e[expr_id as usize] {
             Expr::A(lhs, rhs) => {
-                self.e[expr_id as usize] = self.partial_eval_primitive_application_2(lhs, rhs);
+                self.e[expr_id as usize] = self.partial_eval_primitive_application_2(lhs, rhs)?;
+                Ok(())
             }
             _ => panic!("Not an application!"),
         }
     }
 
-    fn partial_eval_primitive_application_2(&mut self, lhs: ExprId, rhs: ExprId) -> Expr {
+    fn partial_eval_primitive_application_2(&mut self, lhs: ExprId, rhs: ExprId) -> Result<Expr> {
         let rhs = self.drop_i1(rhs);
-        match &self.e[lhs as usize] {
+        Ok(match &self.e[lhs as usize] {
             Expr::K => Expr::K1(rhs),
-            Expr::K1(arg1) => Expr::I1(*arg1),
+            Expr::K1(arg1) => {
+                self.stats.k_reductions += 1;
+                Expr::I1(*arg1)
+            }
             Expr::S => Expr::S1(rhs),
-            Expr::I => Expr::I1(rhs),
+            Expr::I => {
+                self.stats.i_reductions += 1;
+                Expr::I1(rhs)
+            }
             Expr::S1(arg1) => Expr::S2(*arg1, rhs),
-            Expr::LazyRead => self.apply_lazy_read(lhs, rhs),
-            Expr::S2(arg1, arg2) => self.apply_s2(*arg1, *arg2, rhs),
-            Expr::Inc => {
-                let rhs2 = self.partial_eval(rhs);
-                match self.e[rhs2 as usize] {
-                    Expr::Num(num) => Expr::Num(num + 1),
-                    _ => panic!("Attempted to apply inc to a non-number"),
+            Expr::LazyRead => self.apply_lazy_read(lhs, rhs)?,
+            Expr::S2(arg1, arg2) => {
+                self.stats.s_reductions += 1;
+                self.apply_s2(*arg1, *arg2, rhs)?
+            }
+            // Argument was already evaluated by `continue_eval`.
+            Expr::Inc => match self.e[rhs as usize] {
+                Expr::Num(num) => {
+                    self.stats.inc_reductions += 1;
+                    Expr::Num(num.checked_add(1).ok_or(LazyKError::NumeralOverflow)?)
                 }
+                _ => return Err(LazyKError::NotANumeral),
+            },
+            // Numeral applies the function to exactly one argument each time,
+            // so Count must be at the top of the evaluated spine.
+            Expr::Count if self.eval_prev != 0 => return Err(LazyKError::NotANumeral),
+            Expr::Count => {
+                self.count.increment();
+                if let Some(limit) = self.count_limit {
+                    if self.count.to_u64().is_none_or(|count| count > limit) {
+                        return Err(LazyKError::NumeralOverflow);
+                    }
+                }
+                Expr::I1(rhs)
             }
-            _ => panic!("Unreachable code."),
-        }
+            // Num can only be applied to something if program's output is not a numeral.
+            _ => return Err(LazyKError::NotANumeral),
+        })
     }
 
     // lhs points to LazyRead.
-    fn apply_lazy_read(&mut self, lhs: ExprId, rhs: ExprId) -> Expr {
-        let next_char = match self.input.read_byte() {
-            Some(ch) => ch as u16,
+    fn apply_lazy_read(&mut self, lhs: ExprId, rhs: ExprId) -> Result<Expr> {
+        let next_char = match self.input.read_byte()? {
+            Some(ch) => {
+                self.stats.bytes_read += 1;
+                ch as u16
+            }
             None => EOF_MARKER,
         };
+        self.stats.lazy_read_reductions += 1;
         let ch = self.church_char(next_char);
-        let x_rhs = self.new_expr(Expr::K1(ch));
-        let x = self.new_expr(Expr::S2(self.i, x_rhs));
-        let new_lazy_read = self.new_expr(Expr::LazyRead);
-        let y = self.new_expr(Expr::K1(new_lazy_read));
+        let x_rhs = self.new_expr(Expr::K1(ch))?;
+        let x = self.new_expr(Expr::S2(self.i, x_rhs))?;
+        let new_lazy_read = self.new_expr(Expr::LazyRead)?;
+        let y = self.new_expr(Expr::K1(new_lazy_read))?;
         self.e[lhs as usize] = Expr::S2(x, y);
         self.partial_eval_primitive_application_2(lhs, rhs)
     }
 
-    fn apply_s2(&mut self, arg1: ExprId, arg2: ExprId, rhs: ExprId) -> Expr {
-        let new_lhs = self.partial_apply(arg1, rhs);
-        Expr::A(new_lhs, self.partial_apply(arg2, rhs))
+    fn apply_s2(&mut self, arg1: ExprId, arg2: ExprId, rhs: ExprId) -> Result<Expr> {
+        let new_lhs = self.partial_apply(arg1, rhs)?;
+        Ok(Expr::A(new_lhs, self.partial_apply(arg2, rhs)?))
     }
 
-    pub fn partial_apply(&mut self, lhs: ExprId, rhs: ExprId) -> ExprId {
+    /// Builds application of `lhs` to `rhs`. Fails when memory limit is reached.
+    pub fn partial_apply(&mut self, lhs: ExprId, rhs: ExprId) -> Result<ExprId> {
         self.new_expr(Expr::A(lhs, rhs))
     }
 
@@ -202,8 +622,33 @@ impl LazyKRunner {
         expr
     }
 
-    fn partial_eval(&mut self, mut cur: ExprId) -> ExprId {
-        let mut prev: ExprId = 0;
+    // If expr_id is application of Inc to an argument that is not evaluated yet,
+    // returns that argument.
+    fn unevaluated_inc_arg(&self, expr_id: ExprId) -> Option<ExprId> {
+        if let Expr::A(lhs, rhs) = self.e[expr_id as usize] {
+            if let Expr::Inc = self.e[lhs as usize] {
+                let rhs = self.drop_i1(rhs);
+                if !matches!(self.e[rhs as usize], Expr::Num(_)) {
+                    return Some(rhs);
+                }
+            }
+        }
+        None
+    }
+
+    // Reduces expression to weak head normal form.
+    // Returns None if step budget was exhausted, in which case evaluation can
+    // be continued with `continue_eval`.
+    fn partial_eval(&mut self, expr_id: ExprId) -> Result<Option<ExprId>> {
+        self.abandon_eval();
+        self.eval_cur = expr_id;
+        self.eval_prev = 0;
+        self.continue_eval()
+    }
+
+    fn continue_eval(&mut self) -> Result<Option<ExprId>> {
+        let mut cur = self.eval_cur;
+        let mut prev = self.eval_prev;
         loop {
             cur = self.drop_i1(cur);
             while let Expr::A(arg1, _) = &mut self.e[cur as usize] {
@@ -212,7 +657,23 @@ impl LazyKRunner {
                 swap(&mut cur, &mut prev);
             }
             if prev == 0 {
-                return cur;
+                match self.eval_frames.pop() {
+                    None => {
+                        self.eval_cur = 0;
+                        return Ok(Some(cur));
+                    }
+                    // Argument of Inc is evaluated, return to the application.
+                    Some((inc_app, parent)) => {
+                        if !matches!(self.e[cur as usize], Expr::Num(_)) {
+                            self.eval_cur = inc_app;
+                            self.eval_prev = parent;
+                            return Err(LazyKError::NotANumeral);
+                        }
+                        cur = inc_app;
+                        prev = parent;
+                        continue;
+                    }
+                }
             }
 
             if let Expr::A(arg1, _) = &mut self.e[prev as usize] {
@@ -222,34 +683,281 @@ impl LazyKRunner {
             }
             swap(&mut cur, &mut prev);
 
-            self.partial_eval_primitive_application(cur);
+            // Save state, so it can be continued or abandoned if we return
+            // early, and so garbage collector can find and update it.
+            self.eval_cur = cur;
+            self.eval_prev = prev;
+            if self.used_exprs > self.gc_limit {
+                self.garbage_collect();
+                cur = self.eval_cur;
+                prev = self.eval_prev;
+            }
+            if self.steps_left == Some(0) {
+                return Ok(None);
+            }
+            self.check_cancellation()?;
+            if let Some(arg) = self.unevaluated_inc_arg(cur) {
+                self.eval_frames.push((cur, prev));
+                cur = arg;
+                prev = 0;
+                continue;
+            }
+            match self.partial_eval_primitive_application(cur) {
+                // Reading input is the first thing done by its reduction, so
+                // the reduction can be retried later.
+                Err(LazyKError::Io(err)) if err.kind() == io::ErrorKind::WouldBlock => {
+                    if let Some(steps_left) = &mut self.steps_left {
+                        *steps_left += 1;
+                    }
+                    self.waiting_for_input = true;
+                    return Ok(None);
+                }
+                result => result?,
+            }
         }
     }
 
-    pub fn church2int(&mut self, church: ExprId) -> Result<u16> {
-        let inc = self.partial_apply(church, self.inc);
-        let e = self.partial_apply(inc, self.zero);
-        let result_id = self.partial_eval(e);
-        match self.e[result_id as usize] {
+    fn check_cancellation(&mut self) -> Result<()> {
+        if self.steps_until_check > 0 {
+            self.steps_until_check -= 1;
+            return Ok(());
+        }
+        self.steps_until_check = CANCELLATION_CHECK_INTERVAL;
+        if let Some(token) = &self.cancellation_token {
+            if token.is_cancelled() {
+                return Err(LazyKError::Cancelled);
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(LazyKError::TimedOut);
+            }
+        }
+        Ok(())
+    }
+
+    // Restores pointers reversed by unfinished evaluation, so the expressions
+    // it was working on can be safely used again.
+    pub(crate) fn abandon_eval(&mut self) {
+        if self.eval_cur == 0 {
+            return;
+        }
+        let mut cur = self.eval_cur;
+        let mut prev = self.eval_prev;
+        loop {
+            while prev != 0 {
+                if let Expr::A(arg1, _) = &mut self.e[prev as usize] {
+                    swap(arg1, &mut cur);
+                }
+                swap(&mut cur, &mut prev);
+            }
+            match self.eval_frames.pop() {
+                Some((inc_app, parent)) => {
+                    cur = inc_app;
+                    prev = parent;
+                }
+                None => break,
+            }
+        }
+        self.eval_cur = 0;
+    }
+
+    // Finds applications whose first argument points to their parent because
+    // evaluation is unfinished. Returns root of the expression being evaluated,
+    // node that is about to be reduced, and map from such applications to
+    // their actual first arguments.
+    pub(crate) fn unfinished_eval(&self) -> Option<(ExprId, ExprId, HashMap<ExprId, ExprId>)> {
+        if self.eval_cur == 0 {
+            return None;
+        }
+        let mut links = HashMap::new();
+        let mut cur = self.eval_cur;
+        let mut prev = self.eval_prev;
+        let mut frames = self.eval_frames.iter().rev();
+        loop {
+            while prev != 0 {
+                let parent = match self.e[prev as usize] {
+                    Expr::A(parent, _) => parent,
+                    _ => panic!("Unreachable code"),
+                };
+                links.insert(prev, cur);
+                cur = prev;
+                prev = parent;
+            }
+            match frames.next() {
+                Some(&(inc_app, parent)) => {
+                    cur = inc_app;
+                    prev = parent;
+                }
+                None => return Some((cur, self.eval_cur, links)),
+            }
+        }
+    }
+
+    // Builds expression that evaluates to Num if `church` is a Church numeral.
+    fn church2num(&mut self, church: ExprId) -> Result<ExprId> {
+        let inc = self.partial_apply(church, self.inc)?;
+        self.partial_apply(inc, self.zero)
+    }
+
+    fn expect_num(&self, expr_id: ExprId) -> Result<u16> {
+        match self.e[expr_id as usize] {
             Expr::Num(num) => Ok(num),
-            _ => bail!("Program's output is not a church numeral."),
+            _ => Err(LazyKError::NotANumeral),
+        }
+    }
+
+    // Counts applications of function in Church numeral, failing as soon as
+    // the count exceeds `limit`. Unlike Inc, Count doesn't evaluate its argument
+    // and doesn't allocate a new number for every application.
+    pub(crate) fn count_applications(
+        &mut self,
+        church: ExprId,
+        limit: Option<u64>,
+    ) -> Result<BigUint> {
+        self.run_state = None;
+        self.count = BigUint::default();
+        self.count_limit = limit;
+        let f = self.partial_apply(church, self.count_expr)?;
+        let e = self.partial_apply(f, self.zero)?;
+        let result_id = self.partial_eval(e);
+        self.count_limit = None;
+        match result_id? {
+            Some(result_id) if result_id == self.zero => Ok(std::mem::take(&mut self.count)),
+            Some(_) => Err(LazyKError::NotANumeral),
+            None => Err(LazyKError::BudgetExhausted),
+        }
+    }
+
+    /// Reduces expression to weak head normal form and returns its id.
+    ///
+    /// At most `budget` reductions are performed. When the budget is
+    /// exhausted, expression is left partially reduced, which is still
+    /// equivalent to the original.
+    ///
+    /// Any unfinished run is abandoned. Ids of expressions that are not
+    /// reachable from the result or from roots may become invalid.
+    pub fn whnf(&mut self, expr_id: ExprId, budget: Option<u64>) -> Result<ExprId> {
+        self.with_budget(budget, |runner| runner.whnf_expr(expr_id))
+    }
+
+    // Performs reductions in `eval` until `budget` of them is exhausted,
+    // however many evaluations it starts.
+    pub(crate) fn with_budget<T>(
+        &mut self,
+        budget: Option<u64>,
+        eval: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        self.steps_left = budget;
+        let result = eval(self);
+        self.steps_left = None;
+        result
+    }
+
+    // Same as `whnf`, limited by the budget that is currently set.
+    pub(crate) fn whnf_expr(&mut self, expr_id: ExprId) -> Result<ExprId> {
+        self.run_state = None;
+        match self.partial_eval(expr_id)? {
+            Some(result_id) => Ok(result_id),
+            None => {
+                self.abandon_eval();
+                Err(LazyKError::BudgetExhausted)
+            }
+        }
+    }
+
+    /// Reduces expression to normal form, where no reductions are possible
+    /// anywhere inside it, and returns its id. Expressions are reduced in
+    /// normal order, so normal form is found whenever it exists.
+    ///
+    /// At most `budget` reductions are performed. When the budget is
+    /// exhausted, expression is left partially reduced, which is still
+    /// equivalent to the original.
+    ///
+    /// Any unfinished run is abandoned. Ids of expressions that are not
+    /// reachable from the result or from roots may become invalid.
+    pub fn normalize(&mut self, expr_id: ExprId, budget: Option<u64>) -> Result<ExprId> {
+        self.with_budget(budget, |runner| runner.normalize_expr(expr_id))
+    }
+
+    fn normalize_expr(&mut self, expr_id: ExprId) -> Result<ExprId> {
+        let result_id = self.whnf_expr(expr_id)?;
+        self.push_root(result_id);
+        // Expressions whose arguments are yet to be normalized are kept on
+        // temporary roots, so garbage collector can update them.
+        let base = self.temp_roots.len();
+        self.push_root(result_id);
+        let outcome = self.normalize_pending(base);
+        self.temp_roots.truncate(base);
+        let result_id = self.pop_root();
+        outcome.map(|()| result_id)
+    }
+
+    fn normalize_pending(&mut self, base: usize) -> Result<()> {
+        // Shared expressions are normalized once, unless garbage collector
+        // has moved them in the meantime.
+        let mut normalized = HashSet::new();
+        let mut gc_cycles = self.gc_cycles;
+        while self.temp_roots.len() > base {
+            let expr_id = self.pop_root();
+            let expr_id = self.whnf_expr(expr_id)?;
+            if self.gc_cycles != gc_cycles && self.config.compacting_gc {
+                normalized.clear();
+                gc_cycles = self.gc_cycles;
+            }
+            if !normalized.insert(expr_id) {
+                continue;
+            }
+            match self.e[expr_id as usize] {
+                Expr::K1(arg1) | Expr::S1(arg1) => self.push_root(arg1),
+                Expr::S2(arg1, arg2) => {
+                    self.push_root(arg2);
+                    self.push_root(arg1);
+                }
+                _ => {}
+            }
         }
+        Ok(())
+    }
+
+    /// Converts Church numeral to integer.
+    ///
+    /// Any unfinished run is abandoned.
+    pub fn church2int(&mut self, church: ExprId) -> Result<u16> {
+        let count = self.count_applications(church, Some(u16::MAX as u64))?;
+        Ok(count.to_u64().expect("Count is within the limit.") as u16)
+    }
+
+    /// Converts Church numeral to 64-bit integer.
+    ///
+    /// Any unfinished run is abandoned.
+    pub fn church2u64(&mut self, church: ExprId) -> Result<u64> {
+        let count = self.count_applications(church, Some(u64::MAX))?;
+        Ok(count.to_u64().expect("Count is within the limit."))
+    }
+
+    /// Converts Church numeral of any size to integer. Note that decoding
+    /// takes time proportional to the value.
+    ///
+    /// Any unfinished run is abandoned.
+    pub fn church2big(&mut self, church: ExprId) -> Result<BigUint> {
+        self.count_applications(church, None)
     }
 
-    fn car(&mut self, list: ExprId) -> ExprId {
+    pub(crate) fn car(&mut self, list: ExprId) -> Result<ExprId> {
         self.partial_apply(list, self.k)
     }
 
-    fn cdr(&mut self, list: ExprId) -> ExprId {
+    pub(crate) fn cdr(&mut self, list: ExprId) -> Result<ExprId> {
         self.partial_apply(list, self.ki)
     }
 
     // pair(X,Y)F := (FX)Y
     // pair(X,Y) = S(SI(KX))(KY)
-    pub(crate) fn pair(&mut self, x: ExprId, y: ExprId) -> ExprId {
-        let d = self.new_expr(Expr::K1(x));
-        let a = self.new_expr(Expr::S2(self.i, d));
-        let b = self.new_expr(Expr::K1(y));
+    pub(crate) fn pair(&mut self, x: ExprId, y: ExprId) -> Result<ExprId> {
+        let d = self.new_expr(Expr::K1(x))?;
+        let a = self.new_expr(Expr::S2(self.i, d))?;
+        let b = self.new_expr(Expr::K1(y))?;
         self.new_expr(Expr::S2(a, b))
     }
 
@@ -263,28 +971,168 @@ impl LazyKRunner {
     pub fn run(
         &mut self,
         expr_id: ExprId,
-        input: Input,
-        output: &mut Output,
+        input: Box<dyn ByteSource>,
+        output: &mut dyn ByteSink,
         output_limit: Option<usize>,
     ) -> Result<u16> {
+        self.start(expr_id, input)?;
+        self.resume(output, output_limit, None)?.into_exit_code()
+    }
+
+    /// Prepares to run program on given input. Nothing is evaluated until `resume` is called.
+    ///
+    /// Any unfinished run is abandoned, and expressions not reachable from
+    /// `expr_id` may be freed.
+    pub fn start(&mut self, expr_id: ExprId, input: Box<dyn ByteSource>) -> Result<()> {
+        self.abandon_eval();
+        self.reset_stats();
+        self.run_state = None;
+        self.push_root(expr_id);
+        self.collect_garbage_if_due();
+        let expr_id = self.pop_root();
         self.input = input;
-        let lr = self.new_expr(Expr::LazyRead);
-        let mut e = self.partial_apply(expr_id, lr);
-        let mut output_size = 0;
+        let lr = self.new_expr(Expr::LazyRead)?;
+        let list = self.partial_apply(expr_id, lr)?;
+        self.run_state = Some(RunState {
+            list,
+            output_size: 0,
+        });
+        Ok(())
+    }
+
+    /// Continues the run prepared by `start`, performing at most `budget` reductions.
+    pub fn resume(
+        &mut self,
+        output: &mut dyn ByteSink,
+        output_limit: Option<usize>,
+        budget: Option<u64>,
+    ) -> Result<RunOutcome> {
+        Ok(self.resume_with_budget(output, output_limit, budget)?.0)
+    }
+
+    /// Same as `resume` with limited budget, but also returns the number of
+    /// reductions performed.
+    pub(crate) fn resume_counted(
+        &mut self,
+        output: &mut dyn ByteSink,
+        output_limit: Option<usize>,
+        budget: u64,
+    ) -> Result<(RunOutcome, u64)> {
+        let (outcome, steps_left) = self.resume_with_budget(output, output_limit, Some(budget))?;
+        Ok((outcome, budget - steps_left.unwrap_or(0)))
+    }
+
+    // Returns outcome of the run and the part of budget that is left.
+    fn resume_with_budget(
+        &mut self,
+        output: &mut dyn ByteSink,
+        output_limit: Option<usize>,
+        budget: Option<u64>,
+    ) -> Result<(RunOutcome, Option<u64>)> {
+        if self.run_state.is_none() {
+            return Err(LazyKError::NoRunToResume);
+        }
+        self.steps_left = budget;
+        let result = self.resume_run(output, output_limit);
+        let steps_left = self.steps_left;
+        // Only suspended run can be resumed.
+        self.finish_resume(matches!(
+            result,
+            Ok(RunOutcome::BudgetExhausted | RunOutcome::WaitingForInput)
+        ));
+        Ok((result?, steps_left))
+    }
+
+    /// Continues the run prepared by `start` until it outputs one byte,
+    /// performing at most `budget` reductions.
+    pub(crate) fn resume_byte(
+        &mut self,
+        output_limit: Option<usize>,
+        budget: Option<u64>,
+    ) -> Result<OutputStep> {
+        if self.run_state.is_none() {
+            return Err(LazyKError::NoRunToResume);
+        }
+        self.steps_left = budget;
+        let result = self.output_step(output_limit);
+        self.finish_resume(matches!(
+            result,
+            Ok(OutputStep::Byte(_)
+                | OutputStep::Stopped(RunOutcome::BudgetExhausted | RunOutcome::WaitingForInput))
+        ));
+        result
+    }
+
+    fn finish_resume(&mut self, resumable: bool) {
+        self.steps_left = None;
+        if !resumable {
+            self.abandon_eval();
+            self.run_state = None;
+        }
+    }
+
+    // Run state is kept in `self.run_state`, so garbage collector can find it.
+    fn resume_run(
+        &mut self,
+        output: &mut dyn ByteSink,
+        output_limit: Option<usize>,
+    ) -> Result<RunOutcome> {
         loop {
-            let head = self.car(e);
-            let ch = self.church2int(head)?;
-            if ch >= EOF_MARKER {
-                return Ok(ch - EOF_MARKER);
+            match self.output_step(output_limit)? {
+                OutputStep::Byte(byte) => output.write_byte(byte)?,
+                OutputStep::Stopped(outcome) => {
+                    if let RunOutcome::Halted(_) = outcome {
+                        output.flush()?;
+                    }
+                    return Ok(outcome);
+                }
             }
-            output.write_char(ch as u8)?;
-            e = self.cdr(e);
-            output_size += 1;
-            self.garbage_collect(e);
-            if output_limit.is_some() && output_limit.unwrap() == output_size {
-                return Ok(1);
+        }
+    }
+
+    // Evaluates the next element of output list and moves past it.
+    fn output_step(&mut self, output_limit: Option<usize>) -> Result<OutputStep> {
+        if self.eval_cur == 0 && output_limit == Some(self.run_state().output_size) {
+            return Ok(OutputStep::Stopped(RunOutcome::Halted(1)));
+        }
+        let result_id = if self.eval_cur == 0 {
+            let list = self.run_state().list;
+            let head = self.car(list)?;
+            let e = self.church2num(head)?;
+            self.partial_eval(e)?
+        } else {
+            self.continue_eval()?
+        };
+        let result_id = match result_id {
+            Some(result_id) => result_id,
+            None if self.waiting_for_input => {
+                self.waiting_for_input = false;
+                return Ok(OutputStep::Stopped(RunOutcome::WaitingForInput));
             }
+            None => return Ok(OutputStep::Stopped(RunOutcome::BudgetExhausted)),
+        };
+        let ch = self.expect_num(result_id)?;
+        if ch >= EOF_MARKER {
+            return Ok(OutputStep::Stopped(RunOutcome::Halted(ch - EOF_MARKER)));
         }
+        let list = self.run_state().list;
+        let list = self.cdr(list)?;
+        let state = self.run_state();
+        state.list = list;
+        state.output_size += 1;
+        self.stats.bytes_written += 1;
+        Ok(OutputStep::Byte(ch as u8))
+    }
+
+    fn run_state(&mut self) -> &mut RunState {
+        self.run_state
+            .as_mut()
+            .expect("There is no run in progress.")
+    }
+
+    // Remaining output list of unfinished run.
+    pub(crate) fn run_list(&self) -> Option<ExprId> {
+        self.run_state.as_ref().map(|state| state.list)
     }
 
     pub(crate) fn get_expr(&'_ self, expr_id: ExprId) -> &'_ Expr {
@@ -0,0 +1,351 @@
+use crate::{
+    error::{LazyKError, Result},
+    expression::{Expr, ExprId},
+    optimizer::{expr_size, Optimizer},
+    printer::{CcPrinter, GenericPrinter, PoolView},
+    runner::{LazyKRunner, RunnerConfig},
+    term::Term,
+    Style,
+};
+use std::{collections::HashMap, sync::OnceLock};
+
+// Longest Iota code (in number of `i`s) and Jot code (in bits) tried when
+// searching for short encodings.
+static MAX_IOTA_LEAVES: usize = 11;
+static MAX_JOT_LENGTH: usize = 18;
+// Subexpressions with more combinators than this are not looked up in the
+// table of short encodings.
+static MAX_LOOKUP_SIZE: u64 = 24;
+// Limits on reductions and size of canonical form. Expressions that exceed
+// them have no canonical form.
+static CANONICAL_BUDGET: u64 = 200;
+static MAX_CANONICAL_SIZE: u64 = 64;
+
+// Shortest known codes of expressions, keyed by their canonical form. Every
+// code can be used in place of the expression, in the same way as the code
+// of a single combinator.
+type CodeTable = HashMap<String, String>;
+
+/// Prints LazyK program in Jot or Iota style, replacing subexpressions with
+/// shorter equivalent codes where they are known.
+///
+/// Codes are found by trying all short Iota programs and Jot bit strings,
+/// and comparing what they compute with subexpressions of the program.
+/// Expressions are compared by their canonical form: normal form simplified
+/// by `Optimizer`. So the output is an equivalent program, but not
+/// necessarily one with the same combinators.
+pub(crate) struct ShortPrinter<'a> {
+    pool: PoolView<'a>,
+    codes: &'static CodeTable,
+    // Codes of application and combinators, used when no shorter code is known.
+    a: &'static str,
+    atoms: [&'static str; 3],
+}
+
+// Expression copied from the pool, in which every node is a combinator or
+// application of two nodes. Arguments come before applications.
+enum Node {
+    Atom(usize),
+    App(usize, usize),
+}
+
+// Indices of nodes for combinators S, K and I.
+static S: usize = 0;
+static K: usize = 1;
+static I: usize = 2;
+
+impl<'a> ShortPrinter<'a> {
+    pub(crate) fn new(runner: &'a LazyKRunner, style: Style) -> Self {
+        let codes = match style {
+            Style::Jot => jot_codes(),
+            Style::Iota => iota_codes(),
+            _ => panic!("Short encodings are only searched for Jot and Iota."),
+        };
+        let printer = GenericPrinter::new(runner, style);
+        Self {
+            pool: PoolView::new(runner),
+            codes,
+            a: printer.a,
+            atoms: [printer.s, printer.k, printer.i],
+        }
+    }
+
+    pub(crate) fn print(&self, expr_id: ExprId) -> Result<String> {
+        let (nodes, root) = self.flatten(expr_id)?;
+        let mut codes = self.choose_codes(&nodes);
+        // Alone, `i` would be read as identity rather than iota.
+        if codes[root] == Some("i") {
+            codes[root] = None;
+        }
+        let mut output = String::new();
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            match (codes[node], &nodes[node]) {
+                (Some(code), _) => output.push_str(code),
+                (None, &Node::Atom(atom)) => output.push_str(self.atoms[atom]),
+                (None, &Node::App(lhs, rhs)) => {
+                    output.push_str(self.a);
+                    stack.push(rhs);
+                    stack.push(lhs);
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    // Copies expression to a list of nodes, returning index of its root.
+    // Equal subexpressions are copied to the same node, so that each is
+    // looked up in the table once.
+    fn flatten(&self, expr_id: ExprId) -> Result<(Vec<Node>, usize)> {
+        let mut nodes = vec![Node::Atom(S), Node::Atom(K), Node::Atom(I)];
+        let mut apps: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut indices: HashMap<ExprId, usize> = HashMap::new();
+        let mut stack = vec![expr_id];
+        while let Some(&top) = stack.last() {
+            if indices.contains_key(&top) {
+                stack.pop();
+                continue;
+            }
+            let pending: Vec<ExprId> = match self.pool.get(top) {
+                Expr::A(arg1, arg2) | Expr::S2(arg1, arg2) => vec![arg1, arg2],
+                Expr::K1(arg) | Expr::S1(arg) | Expr::I1(arg) => vec![arg],
+                Expr::S | Expr::K | Expr::I => vec![],
+                _ => return Err(LazyKError::UnprintableExpression),
+            };
+            let pending: Vec<ExprId> = pending
+                .into_iter()
+                .filter(|arg| !indices.contains_key(arg))
+                .collect();
+            if !pending.is_empty() {
+                stack.extend(pending);
+                continue;
+            }
+            let mut app = |lhs: usize, rhs: usize| {
+                *apps.entry((lhs, rhs)).or_insert_with(|| {
+                    nodes.push(Node::App(lhs, rhs));
+                    nodes.len() - 1
+                })
+            };
+            let index = match self.pool.get(top) {
+                Expr::A(arg1, arg2) => app(indices[&arg1], indices[&arg2]),
+                Expr::K1(arg) => app(K, indices[&arg]),
+                Expr::S1(arg) => app(S, indices[&arg]),
+                Expr::S2(arg1, arg2) => {
+                    let s_arg1 = app(S, indices[&arg1]);
+                    app(s_arg1, indices[&arg2])
+                }
+                Expr::I1(arg) => indices[&arg],
+                Expr::S => S,
+                Expr::K => K,
+                _ => I,
+            };
+            indices.insert(top, index);
+            stack.pop();
+        }
+        Ok((nodes, indices[&expr_id]))
+    }
+
+    // Finds the shortest way to print every node. Returns code from the
+    // table for nodes printed with one, and None for the rest.
+    fn choose_codes(&self, nodes: &[Node]) -> Vec<Option<&'static str>> {
+        let mut canonicalizer = Canonicalizer::new();
+        let mut codes = Vec::with_capacity(nodes.len());
+        let mut lengths: Vec<u64> = Vec::with_capacity(nodes.len());
+        let mut sizes: Vec<u64> = Vec::with_capacity(nodes.len());
+        for (index, node) in nodes.iter().enumerate() {
+            let (length, size) = match *node {
+                Node::Atom(atom) => (self.atoms[atom].len() as u64, 1),
+                Node::App(lhs, rhs) => (
+                    (self.a.len() as u64)
+                        .saturating_add(lengths[lhs])
+                        .saturating_add(lengths[rhs]),
+                    sizes[lhs].saturating_add(sizes[rhs]),
+                ),
+            };
+            let code = if size <= MAX_LOOKUP_SIZE {
+                let term = to_term(nodes, index);
+                canonicalizer
+                    .term_key(&term)
+                    .and_then(|key| self.codes.get(&key))
+                    .map(|code| code.as_str())
+                    .filter(|code| (code.len() as u64) < length)
+            } else {
+                None
+            };
+            lengths.push(code.map_or(length, |code| code.len() as u64));
+            sizes.push(size);
+            codes.push(code);
+        }
+        codes
+    }
+}
+
+fn to_term(nodes: &[Node], index: usize) -> Term {
+    match nodes[index] {
+        Node::Atom(atom) => [Term::S, Term::K, Term::I][atom].clone(),
+        Node::App(lhs, rhs) => to_term(nodes, lhs).app(to_term(nodes, rhs)),
+    }
+}
+
+// Brings expressions to canonical form, so that equivalent expressions are
+// likely to have the same one.
+struct Canonicalizer {
+    runner: LazyKRunner,
+}
+
+impl Canonicalizer {
+    fn new() -> Self {
+        let config = RunnerConfig {
+            initial_capacity: 1 << 16,
+            gc_threshold_bytes: 1 << 20,
+            ..RunnerConfig::default()
+        };
+        Self {
+            runner: LazyKRunner::with_config(config),
+        }
+    }
+
+    // Returns canonical form of expression, which is built from A, S, K
+    // and I nodes only.
+    fn canonical(&mut self, expr_id: ExprId) -> Option<ExprId> {
+        let expr_id = self
+            .runner
+            .normalize(expr_id, Some(CANONICAL_BUDGET))
+            .ok()?;
+        let expr_id = Optimizer::new(&mut self.runner).optimize(expr_id).ok()?;
+        if expr_size(&self.runner, expr_id) > MAX_CANONICAL_SIZE {
+            return None;
+        }
+        Some(expr_id)
+    }
+
+    fn key(&self, expr_id: ExprId) -> String {
+        CcPrinter::new(&self.runner)
+            .print(expr_id)
+            .expect("Canonical form is printable.")
+    }
+
+    fn expr_key(&mut self, expr_id: ExprId) -> Option<String> {
+        let expr_id = self.canonical(expr_id)?;
+        Some(self.key(expr_id))
+    }
+
+    fn term_key(&mut self, term: &Term) -> Option<String> {
+        let expr_id = term.build(&mut self.runner).ok()?;
+        self.expr_key(expr_id)
+    }
+}
+
+// Adds code to the table, unless a code for the same expression was found
+// earlier. Codes are tried from the shortest, so the first one is kept.
+fn add_code(codes: &mut CodeTable, key: String, code: String) {
+    codes.entry(key).or_insert(code);
+}
+
+/// Returns table of shortest Iota codes, searched on first use.
+///
+/// Iota code of application is `*` followed by codes of both sides, so every
+/// Iota program is a code of the expression it computes.
+fn iota_codes() -> &'static CodeTable {
+    static CODES: OnceLock<CodeTable> = OnceLock::new();
+    CODES.get_or_init(|| {
+        let mut canonicalizer = Canonicalizer::new();
+        let mut codes = CodeTable::new();
+        // Programs with given number of `i`s.
+        let mut programs: Vec<Vec<String>> = vec![vec![], vec!["i".to_string()]];
+        for leaves in 2..=MAX_IOTA_LEAVES {
+            let mut with_leaves = Vec::new();
+            for lhs_leaves in 1..leaves {
+                for lhs in &programs[lhs_leaves] {
+                    for rhs in &programs[leaves - lhs_leaves] {
+                        with_leaves.push(format!("*{}{}", lhs, rhs));
+                    }
+                }
+            }
+            programs.push(with_leaves);
+        }
+        for program in programs.into_iter().flatten() {
+            // Alone, `i` is read as identity rather than as iota.
+            let key = if program == "i" {
+                canonicalizer.expr_key(canonicalizer.runner.iota)
+            } else {
+                canonicalizer.term_key(&Term::Source(program.clone()))
+            };
+            if let Some(key) = key {
+                add_code(&mut codes, key, program);
+            }
+        }
+        codes
+    })
+}
+
+// Jot program read so far, as a function of program `f` that precedes it.
+// Since `f` is unknown, the only reductions are of `S (K x)` applied to two
+// arguments.
+#[derive(Clone)]
+enum JotState {
+    // `f` applied to arguments.
+    Applied(Vec<Term>),
+    // `S (K x)` applied to at most one argument.
+    Composed(Box<JotState>, Option<Term>),
+}
+
+impl JotState {
+    fn apply(self, arg: Term) -> JotState {
+        match self {
+            JotState::Applied(mut args) => {
+                args.push(arg);
+                JotState::Applied(args)
+            }
+            JotState::Composed(x, None) => JotState::Composed(x, Some(arg)),
+            // S (K x) y z = x (y z)
+            JotState::Composed(x, Some(y)) => x.apply(y.app(arg)),
+        }
+    }
+
+    fn push_bit(self, bit: char) -> JotState {
+        match bit {
+            '0' => self.apply(Term::S).apply(Term::K),
+            _ => JotState::Composed(Box::new(self), None),
+        }
+    }
+}
+
+/// Returns table of shortest Jot codes, searched on first use.
+///
+/// Jot program is read left to right, with `0` turning program `f` read so
+/// far into `f S K`, and `1` into `\x y. f (x y)`. So appending `1` followed
+/// by codes of `x` and `y` turns `f` into `f (x y)`, if code of `x` is a bit
+/// string that turns any `f` into `f x`. The search looks for such strings.
+fn jot_codes() -> &'static CodeTable {
+    static CODES: OnceLock<CodeTable> = OnceLock::new();
+    CODES.get_or_init(|| {
+        let mut canonicalizer = Canonicalizer::new();
+        let mut codes = CodeTable::new();
+        // States after reading all bit strings of the same length. Those in
+        // which `f` has more than one argument are dropped, as arguments are
+        // never removed.
+        let mut states = vec![(JotState::Applied(Vec::new()), String::new())];
+        for _ in 0..MAX_JOT_LENGTH {
+            let mut next_states = Vec::new();
+            for (state, code) in states {
+                for bit in ['0', '1'] {
+                    let state = state.clone().push_bit(bit);
+                    let code = format!("{}{}", code, bit);
+                    match &state {
+                        JotState::Applied(args) if args.len() > 1 => continue,
+                        JotState::Applied(args) if args.len() == 1 => {
+                            if let Some(key) = canonicalizer.term_key(&args[0]) {
+                                add_code(&mut codes, key, code.clone());
+                            }
+                        }
+                        _ => {}
+                    }
+                    next_states.push((state, code));
+                }
+            }
+            states = next_states;
+        }
+        codes
+    })
+}
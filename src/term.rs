@@ -0,0 +1,85 @@
+use crate::{
+    error::{LazyKError, Result},
+    expression::{Expr, ExprId},
+    parser::Parser,
+    printer::PoolView,
+    runner::LazyKRunner,
+};
+
+/// Lazy K expression built by the caller, e.g. to be passed as an argument
+/// to `LazyKProgram::apply`.
+///
+/// ```
+/// use lazyk_rust::Term;
+/// let skk = Term::S.app(Term::K).app(Term::K);
+/// let parsed = Term::source("\\x. x");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Term {
+    S,
+    K,
+    I,
+    App(Box<Term>, Box<Term>),
+    /// Source code in any syntax accepted by `LazyKProgram::compile`.
+    Source(String),
+}
+
+/// How far `LazyKProgram::apply` reduces the result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reduction {
+    /// Weak head normal form: only until the result is a combinator without
+    /// enough arguments to be reduced. Its arguments are left as they are.
+    WeakHead,
+    /// Normal form: until no reductions are possible anywhere in the result.
+    Normal,
+}
+
+/// Result of `LazyKProgram::apply`, kept alive by the program until it is
+/// released with `LazyKProgram::release`.
+#[derive(Debug)]
+pub struct TermHandle {
+    pub(crate) root: usize,
+}
+
+impl Term {
+    /// Applies this term to `arg`.
+    pub fn app(self, arg: Term) -> Term {
+        Term::App(Box::new(self), Box::new(arg))
+    }
+
+    pub fn source(source: &str) -> Term {
+        Term::Source(source.to_string())
+    }
+
+    pub(crate) fn build(&self, runner: &mut LazyKRunner) -> Result<ExprId> {
+        Ok(match self {
+            Term::S => runner.s,
+            Term::K => runner.k,
+            Term::I => runner.i,
+            Term::App(lhs, rhs) => {
+                let lhs = lhs.build(runner)?;
+                let rhs = rhs.build(runner)?;
+                runner.partial_apply(lhs, rhs)?
+            }
+            Term::Source(source) => Parser::parse(source, runner)?,
+        })
+    }
+
+    /// Converts expression to a term. Shared subexpressions are copied.
+    pub(crate) fn from_expr(pool: &PoolView, expr_id: ExprId) -> Result<Term> {
+        Ok(match pool.get(expr_id) {
+            Expr::A(arg1, arg2) => Self::from_expr(pool, arg1)?.app(Self::from_expr(pool, arg2)?),
+            Expr::K => Term::K,
+            Expr::K1(arg) => Term::K.app(Self::from_expr(pool, arg)?),
+            Expr::S => Term::S,
+            Expr::S1(arg) => Term::S.app(Self::from_expr(pool, arg)?),
+            Expr::S2(arg1, arg2) => Term::S
+                .app(Self::from_expr(pool, arg1)?)
+                .app(Self::from_expr(pool, arg2)?),
+            Expr::I => Term::I,
+            // Indirection left by reduction, equivalent to its argument.
+            Expr::I1(arg) => Self::from_expr(pool, arg)?,
+            _ => return Err(LazyKError::UnprintableExpression),
+        })
+    }
+}
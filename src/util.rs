@@ -31,9 +31,37 @@ fn num_repr_no_high_powers(x: u16) -> NumRepr {
     // Try products of 2 numbers.
     let sqrt = (x as f32).sqrt().floor() as u16;
     for d in (2..=sqrt).rev() {
-        if x % d == 0 {
+        if x.is_multiple_of(d) {
             return NumRepr::Mul(d as usize, (x / d) as usize);
         }
     }
     NumRepr::Inc((x - 1) as usize)
 }
+
+/// Set of small integers, stored as one bit per element.
+#[derive(Default)]
+pub(crate) struct BitSet {
+    words: Vec<u64>,
+}
+
+impl BitSet {
+    /// Removes all elements and makes room for elements less than `n`.
+    /// Memory is reused between calls.
+    pub(crate) fn reset(&mut self, n: usize) {
+        self.words.clear();
+        self.words.resize(n.div_ceil(64), 0);
+    }
+
+    /// Adds element to the set. Returns whether it wasn't there before.
+    pub(crate) fn insert(&mut self, i: usize) -> bool {
+        let word = &mut self.words[i / 64];
+        let mask = 1 << (i % 64);
+        let inserted = *word & mask == 0;
+        *word |= mask;
+        inserted
+    }
+
+    pub(crate) fn contains(&self, i: usize) -> bool {
+        self.words[i / 64] & (1 << (i % 64)) != 0
+    }
+}
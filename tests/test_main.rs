@@ -1,5 +1,5 @@
 use assert_cmd::Command;
-use predicates::str::contains;
+use predicates::{prelude::PredicateBooleanExt, str::contains};
 
 #[test]
 fn called_with_no_args() {
@@ -37,8 +37,8 @@ fn parse_error() {
         .unwrap()
         .args(["-e", "SK("])
         .assert()
-        .success()
-        .stdout("Parsing error: Premature end of program.\n");
+        .code(65)
+        .stderr("Parsing error: 1:3: Unmatched open-parenthesis.\nSK(\n  ^\n");
 }
 
 #[test]
@@ -48,8 +48,20 @@ fn runtime_error() {
         .args(["-e", "KK"])
         .write_stdin("a")
         .assert()
+        .code(70)
+        .stderr("Runtime error: Program\'s output is not a church numeral.\n");
+}
+
+#[test]
+fn prints_stats() {
+    Command::cargo_bin("lazyk-rust")
+        .unwrap()
+        .args(["-e", "I", "--stats"])
+        .write_stdin("abc")
+        .assert()
         .success()
-        .stdout("Runtime error: Program\'s output is not a church numeral.\n");
+        .stdout("abc")
+        .stderr(contains("Bytes read: 3, written: 3\n").and(contains("LazyRead: 4")));
 }
 
 #[test]
@@ -61,3 +73,179 @@ fn executes_program_from_file() {
         .success()
         .stdout("Hello, world!\n");
 }
+
+#[test]
+fn exits_with_program_exit_code() {
+    // Outputs Church numeral 259 as the first character.
+    let source = "K(K(S(S(KS)K)(S(S(KS)K)(S(S(KS)K)(SII(S(S(KS)K)I(S(S(KS)K)I)))))))";
+    Command::cargo_bin("lazyk-rust")
+        .unwrap()
+        .args(["-e", source])
+        .assert()
+        .code(3)
+        .stdout("");
+}
+
+#[test]
+fn exit_code_above_255_is_failure() {
+    // Outputs Church numeral 512 (2^9), which is exit code 256.
+    let source = "K (K ((\\f x. f (f (f (f (f (f (f (f (f x))))))))) (\\f x. f (f x))))";
+    Command::cargo_bin("lazyk-rust")
+        .unwrap()
+        .args(["-e", source])
+        .assert()
+        .code(255)
+        .stdout("");
+}
+
+#[test]
+fn missing_program_file() {
+    Command::cargo_bin("lazyk-rust")
+        .unwrap()
+        .args(["./examples/does_not_exist.lazy"])
+        .assert()
+        .code(66)
+        .stderr(contains("Could not read source"));
+}
+
+#[test]
+fn repl() {
+    Command::cargo_bin("lazyk-rust")
+        .unwrap()
+        .arg("repl")
+        .write_stdin(
+            "cdr = \\l. l (K I)\n\
+             :input Hello\n\
+             \\x. cdr (cdr x)\n\
+             :show unlambda \\x y. y x\n\
+             :show cc SKK(KS)(SII)\n\
+             SII(SII)\n\
+             :defs\n\
+             cdr (\n\
+             :quit\n\
+             I\n",
+        )
+        .assert()
+        .success()
+        .stdout(contains("> Defined cdr.\n"))
+        .stdout(contains("> llo\n"))
+        .stdout(contains("> ``s`k`sik\n"))
+        .stdout(contains("> S\n"))
+        .stdout(contains("> Error: Reduction budget exhausted.\n"))
+        .stdout(contains("> cdr = \\l. l (K I);\n"))
+        .stdout(contains(
+            "> Parsing error: Unmatched open-parenthesis.\ncdr (\n    ^\n",
+        ))
+        .stdout(contains("Hello").not());
+}
+
+#[test]
+fn converts_program() {
+    Command::cargo_bin("lazyk-rust")
+        .unwrap()
+        .args(["convert", "-e", "``s`kk`ki", "--style", "cc"])
+        .assert()
+        .success()
+        .stdout("S(KK)(KI)\n");
+    Command::cargo_bin("lazyk-rust")
+        .unwrap()
+        .args([
+            "convert",
+            "-e",
+            "x = SKK; x x",
+            "-s",
+            "unlambda",
+            "--shared",
+        ])
+        .assert()
+        .success()
+        .stdout("d0 = ``skk;\n`d0 d0\n");
+}
+
+#[test]
+fn converts_program_with_wrapping() {
+    let expected = include_str!("../examples/hallo_welt_3.lazy");
+    let output = Command::cargo_bin("lazyk-rust")
+        .unwrap()
+        .args([
+            "convert",
+            "./examples/hallo_welt_1.lazy",
+            "-s",
+            "jot",
+            "-w",
+            "80",
+        ])
+        .output()
+        .unwrap();
+    let output = String::from_utf8(output.stdout).unwrap();
+    assert!(output.lines().all(|line| line.len() <= 80));
+    assert_eq!(output.replace('\n', ""), expected);
+
+    // Names are not broken.
+    Command::cargo_bin("lazyk-rust")
+        .unwrap()
+        .args(["convert", "-e", "x = SKK; x x", "--shared", "-w", "4"])
+        .assert()
+        .success()
+        .stdout("d0 =\n SKK\n;\nd0 \nd0\n");
+}
+
+#[test]
+fn normalizes_program() {
+    Command::cargo_bin("lazyk-rust")
+        .unwrap()
+        .args([
+            "normalize",
+            "-e",
+            "two = \\f x. f (f x); two two",
+            "-s",
+            "unlambda",
+        ])
+        .assert()
+        .success()
+        .stdout("``s`k``s``s`kski``s``s`kski\n");
+    Command::cargo_bin("lazyk-rust")
+        .unwrap()
+        .args(["normalize", "-e", "SII(SII)", "--budget", "1000"])
+        .assert()
+        .code(70)
+        .stderr("Could not normalize program: Reduction budget exhausted.\n");
+}
+
+#[test]
+fn optimizes_program() {
+    Command::cargo_bin("lazyk-rust")
+        .unwrap()
+        .args(["convert", "-e", "S(K(SKK))(K(KI))", "--optimize"])
+        .assert()
+        .success()
+        .stdout("K(KI)\n")
+        .stderr("Optimized program size: 8 -> 3 combinators.\n");
+}
+
+#[test]
+fn converts_program_to_short_source() {
+    Command::cargo_bin("lazyk-rust")
+        .unwrap()
+        .args(["convert", "-e", "SK", "--style", "jot", "--short"])
+        .assert()
+        .success()
+        .stdout("10\n");
+}
+
+#[test]
+fn compresses_text() {
+    let output = Command::cargo_bin("lazyk-rust")
+        .unwrap()
+        .args(["compress", "examples/ab.lazy", "--style", "iota"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let source = String::from_utf8(output.stdout).unwrap();
+    Command::cargo_bin("lazyk-rust")
+        .unwrap()
+        .args(["-e", &source])
+        .assert()
+        .success()
+        .stdout(include_str!("../examples/ab.lazy"));
+}
@@ -1,6 +1,22 @@
 use anyhow::Result;
-use lazyk_rust::{LazyKProgram, LazyKRunner, Style};
-use std::ops::Deref;
+use lazyk_rust::{
+    AsyncByteSource, BigUint, CancellationToken, LazyKError, LazyKProgram, LazyKRunner, ReadSource,
+    Reduction, RunOutcome, RunnerConfig, Style, Term, WriteSink,
+};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io::{self, ErrorKind},
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::channel,
+        Arc,
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread,
+    time::{Duration, Instant},
+};
 
 #[test]
 fn test_church2int() {
@@ -104,24 +120,64 @@ fn test_abab() -> Result<()> {
     Ok(())
 }
 
-fn assert_error<T>(x: Result<T>, expected_message: &str) {
+fn assert_error<T>(x: Result<T, LazyKError>, expected_message: &str) {
     match x {
         Ok(_) => panic!("Expected error, got Ok."),
-        Err(err) => assert_eq!(err.root_cause().deref().to_string(), expected_message),
+        Err(err) => assert_eq!(err.to_string(), expected_message),
     }
 }
 
 #[test]
 fn test_parse_errors() -> Result<()> {
-    assert_error(LazyKProgram::compile("((("), "Premature end of program.");
-    assert_error(LazyKProgram::compile("abcd"), "Invalid character: [a]");
+    assert_error(
+        LazyKProgram::compile("((("),
+        "1:3: Unmatched open-parenthesis.",
+    );
+    assert_error(LazyKProgram::compile("abcd"), "1:1: Invalid character: [a]");
     assert_error(
         LazyKProgram::compile("(KS))"),
-        "Unmatched trailing close-parenthesis.",
+        "1:5: Unmatched trailing close-parenthesis.",
+    );
+    assert_error(
+        LazyKProgram::compile("`K"),
+        "1:3: Unexpected end of source.",
+    );
+    assert_error(
+        LazyKProgram::compile("(`K)"),
+        "1:4: Mismatched close-parenthesis!",
     );
     Ok(())
 }
 
+#[test]
+fn test_parse_error_location() {
+    let source = "# Comment.\nS(K\n  (SKK)\tx)\n";
+    let err = match LazyKProgram::compile(source) {
+        Err(LazyKError::Parse(err)) => err,
+        _ => panic!("Expected parse error."),
+    };
+    assert_eq!(err.message, "Invalid character: [x]");
+    assert_eq!((err.line, err.column, err.offset), (3, 9, 23));
+    assert_eq!(err.source_line, "  (SKK)\tx)");
+    assert_eq!(err.snippet(), "  (SKK)\tx)\n       \t^");
+
+    let err = match LazyKProgram::compile("SK\n(S\n(KK)") {
+        Err(LazyKError::Parse(err)) => err,
+        _ => panic!("Expected parse error."),
+    };
+    assert_eq!(err.message, "Unmatched open-parenthesis.");
+    assert_eq!((err.line, err.column, err.offset), (2, 1, 3));
+
+    // Carriage return at the end of source isn't part of the line.
+    let err = match LazyKProgram::compile("`K\r") {
+        Err(LazyKError::Parse(err)) => err,
+        _ => panic!("Expected parse error."),
+    };
+    assert_eq!(err.message, "Unexpected end of source.");
+    assert_eq!((err.line, err.column, err.offset), (1, 3, 3));
+    assert_eq!(err.snippet(), "`K\n  ^");
+}
+
 #[test]
 fn test_runtime_errors() -> Result<()> {
     let mut program = LazyKProgram::compile("KSKSKSKKS")?;
@@ -129,6 +185,311 @@ fn test_runtime_errors() -> Result<()> {
         program.run_string(""),
         "Program's output is not a church numeral.",
     );
+    // Applies the numeral to itself, so Num ends up in function position.
+    let mut program = LazyKProgram::compile("K(S(SI(K(SII)))K)")?;
+    assert!(matches!(
+        program.run_string(""),
+        Err(LazyKError::NotANumeral)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_numeral_overflow() -> Result<()> {
+    let mut pool = LazyKRunner::new();
+    let two = pool.church_char(2);
+    let big = pool.partial_apply(two, pool.church_char(256))?;
+    assert!(matches!(
+        pool.church2int(big),
+        Err(LazyKError::NumeralOverflow)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_run_with() -> Result<()> {
+    let mut program = LazyKProgram::compile(include_str!("../examples/reverse.lazy"))?;
+
+    // Reader and writer.
+    let mut output = WriteSink::new(Vec::new());
+    let exit_code = program.run_with(ReadSource::new("stressed".as_bytes()), &mut output)?;
+    assert_eq!(exit_code, 0);
+    assert_eq!(output.into_inner(), b"desserts");
+
+    // Channels, with input written by another thread.
+    let (input_sender, input_receiver) = channel();
+    let (mut output_sender, output_receiver) = channel();
+    let writer = thread::spawn(move || {
+        for byte in "abc".bytes() {
+            input_sender.send(byte).unwrap();
+        }
+    });
+    program.run_with(input_receiver, &mut output_sender)?;
+    writer.join().unwrap();
+    drop(output_sender);
+    assert_eq!(output_receiver.iter().collect::<Vec<u8>>(), b"cba");
+
+    // Callbacks.
+    let mut bytes = "xy".bytes();
+    let mut output = Vec::new();
+    let mut sink = |byte: u8| -> io::Result<()> {
+        output.push(byte);
+        Ok(())
+    };
+    program.run_with(move || Ok(bytes.next()), &mut sink)?;
+    assert_eq!(output, b"yx");
+    Ok(())
+}
+
+#[test]
+fn test_run_with_io_errors() -> Result<()> {
+    let mut program = LazyKProgram::compile("I")?;
+    let source = || -> io::Result<Option<u8>> { Err(ErrorKind::ConnectionReset.into()) };
+    match program.run_with(source, &mut Vec::new()) {
+        Err(LazyKError::Io(err)) => assert_eq!(err.kind(), ErrorKind::ConnectionReset),
+        result => panic!("Unexpected result: {:?}", result),
+    }
+
+    let mut sink = |_: u8| -> io::Result<()> { Err(ErrorKind::BrokenPipe.into()) };
+    match program.run_with(ReadSource::new("a".as_bytes()), &mut sink) {
+        Err(LazyKError::Io(err)) => assert_eq!(err.kind(), ErrorKind::BrokenPipe),
+        result => panic!("Unexpected result: {:?}", result),
+    }
+
+    // Program can still be run after errors.
+    assert_eq!(program.run_string("abc")?, "abc");
+    Ok(())
+}
+
+#[test]
+fn test_output_iter() -> Result<()> {
+    // Infinite output is consumed incrementally.
+    let mut program = LazyKProgram::compile(include_str!("../examples/primes.lazy"))?;
+    let primes: Vec<u8> = program
+        .output_iter(io::empty())
+        .map(|byte| byte.unwrap())
+        .take_while(|&byte| byte != b'3')
+        .collect();
+    assert_eq!(primes, b"2\n");
+    assert_eq!(program.exit_code(), None);
+
+    // Iteration ends when program halts.
+    let mut program = LazyKProgram::compile(include_str!("../examples/reverse.lazy"))?;
+    let output: Vec<u8> = program
+        .output_iter(ReadSource::new("abc".as_bytes()))
+        .collect::<std::result::Result<_, _>>()?;
+    assert_eq!(output, b"cba");
+    assert_eq!(program.exit_code(), Some(0));
+
+    // Output limit and step budget apply.
+    program.set_output_limit(Some(2));
+    let output: Vec<u8> = program
+        .output_iter(ReadSource::new("abc".as_bytes()))
+        .collect::<std::result::Result<_, _>>()?;
+    assert_eq!(output, b"cb");
+    assert_eq!(program.exit_code(), Some(1));
+    let mut program = LazyKProgram::compile("SII(SII)")?;
+    program.set_step_budget(Some(1000));
+    let mut output = program.output_iter(io::empty());
+    assert!(matches!(
+        output.next(),
+        Some(Err(LazyKError::BudgetExhausted))
+    ));
+    assert!(output.next().is_none());
+    Ok(())
+}
+
+// Waker that records whether it was called.
+struct Flag(AtomicBool);
+
+impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+// Polls future until it's ready, checking that it's woken before every
+// poll. Returns result and number of polls.
+fn block_on<F: Future>(future: F) -> (F::Output, usize) {
+    let mut future = pin!(future);
+    let flag = Arc::new(Flag(AtomicBool::new(true)));
+    let waker = Waker::from(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+    for polls in 1.. {
+        assert!(
+            flag.0.swap(false, Ordering::SeqCst),
+            "Future was not woken."
+        );
+        if let Poll::Ready(result) = future.as_mut().poll(&mut cx) {
+            return (result, polls);
+        }
+    }
+    unreachable!()
+}
+
+// Source whose bytes, and end of input, are available on every other poll.
+struct SlowSource {
+    bytes: VecDeque<u8>,
+    ready: bool,
+}
+
+impl AsyncByteSource for SlowSource {
+    fn poll_read_byte(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Option<u8>>> {
+        self.ready = !self.ready;
+        if self.ready {
+            Poll::Ready(Ok(self.bytes.pop_front()))
+        } else {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+#[test]
+fn test_run_async() -> Result<()> {
+    // Future yields every given number of reductions.
+    let mut program = LazyKProgram::compile(include_str!("../examples/hello_world.lazy"))?;
+    let mut output = Vec::new();
+    let (result, polls) = block_on(program.run_async(io::empty(), &mut output, 100));
+    assert_eq!(result?, 0);
+    assert_eq!(String::from_utf8(output)?, "Hello, world!\n");
+    assert!(polls > 10);
+
+    // Future waits for input.
+    let mut program = LazyKProgram::compile(include_str!("../examples/reverse.lazy"))?;
+    let input = SlowSource {
+        bytes: VecDeque::from(b"stressed".to_vec()),
+        ready: false,
+    };
+    let mut output = Vec::new();
+    let (result, polls) = block_on(program.run_async(input, &mut output, 1_000_000));
+    assert_eq!(result?, 0);
+    assert_eq!(output, b"desserts");
+    // Every byte except the first, and end of input, was pending once.
+    assert_eq!(polls, 9);
+    assert_eq!(program.exit_code(), Some(0));
+
+    // Step budget limits the whole run.
+    let mut program = LazyKProgram::compile("SII(SII)")?;
+    program.set_step_budget(Some(10000));
+    let (result, polls) = block_on(program.run_async(io::empty(), &mut Vec::new(), 1000));
+    assert!(matches!(result, Err(LazyKError::BudgetExhausted)));
+    assert_eq!(polls, 11);
+    Ok(())
+}
+
+#[test]
+fn test_cancellation() -> Result<()> {
+    let mut program = LazyKProgram::compile("SII(SII)")?;
+    let token = CancellationToken::new();
+    program.set_cancellation_token(Some(token.clone()));
+    let canceller = {
+        let token = token.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            token.cancel();
+        })
+    };
+    assert!(matches!(
+        program.run_vec(vec![]),
+        Err(LazyKError::Cancelled)
+    ));
+    canceller.join().unwrap();
+
+    // Runs fail until the token is reset, after which program can be used again.
+    let mut program = LazyKProgram::compile(include_str!("../examples/reverse.lazy"))?;
+    program.set_cancellation_token(Some(token.clone()));
+    assert!(matches!(
+        program.run_string("abc"),
+        Err(LazyKError::Cancelled)
+    ));
+    let output = program.output_iter(io::empty()).collect::<Vec<_>>();
+    assert!(matches!(output[..], [Err(LazyKError::Cancelled)]));
+    token.reset();
+    assert_eq!(program.run_string("abc")?, "cba");
+
+    // Cancelled run leaves the program intact.
+    let text = "Hello, world!";
+    let source = LazyKProgram::make_printer(text.as_bytes()).to_source(Style::CombCalculus)?;
+    let mut program = LazyKProgram::compile(&source)?;
+    program.set_cancellation_token(Some(token.clone()));
+    token.cancel();
+    assert!(matches!(program.run_string(""), Err(LazyKError::Cancelled)));
+    let printed = program.to_source(Style::CombCalculus)?;
+    assert_eq!(LazyKProgram::compile(&printed)?.run_string("")?, text);
+    program.optimize()?;
+    token.reset();
+    assert_eq!(program.run_string("")?, text);
+    Ok(())
+}
+
+#[test]
+fn test_timeout() -> Result<()> {
+    let mut program = LazyKProgram::compile("SII(SII)")?;
+    program.set_timeout(Some(Duration::from_millis(20)));
+    let start = Instant::now();
+    assert!(matches!(program.run_vec(vec![]), Err(LazyKError::TimedOut)));
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    assert!(matches!(
+        program.apply(&[], Reduction::Normal),
+        Err(LazyKError::TimedOut)
+    ));
+    assert_eq!(LazyKError::TimedOut.to_string(), "Evaluation timed out.");
+
+    // Every call is given the full timeout.
+    let mut program = LazyKProgram::compile(include_str!("../examples/reverse.lazy"))?;
+    program.set_timeout(Some(Duration::from_secs(60)));
+    for _ in 0..3 {
+        assert_eq!(program.run_string("abc")?, "cba");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_run_stats() -> Result<()> {
+    let mut program = LazyKProgram::compile("I")?;
+    program.run_string("abc")?;
+    let stats = program.stats().clone();
+    assert_eq!((stats.bytes_read, stats.bytes_written), (3, 3));
+    // Input is read once for every byte and once for end of input.
+    assert_eq!(stats.lazy_read_reductions, 4);
+    assert!(stats.s_reductions > 0 && stats.k_reductions > 0 && stats.inc_reductions > 0);
+    assert!(stats.allocations > 0);
+    assert_eq!(stats.gc_cycles, 0);
+
+    // Counters are reset for every run. Program's expressions reduced by
+    // the first run stay reduced, so the second one does less work.
+    program.run_string("abc")?;
+    assert_eq!(program.stats().bytes_written, 3);
+    assert!(program.stats().s_reductions <= stats.s_reductions);
+
+    // Runs continued with `resume` are counted as a whole.
+    let source = include_str!("../examples/hello_world.lazy");
+    let mut program = LazyKProgram::compile(source)?;
+    program.run_string("")?;
+    let stats = program.stats().clone();
+    let mut program = LazyKProgram::compile(source)?;
+    program.set_step_budget(Some(100));
+    let mut outcome = program.start_vec(vec![])?;
+    while outcome == RunOutcome::BudgetExhausted {
+        outcome = program.resume(100)?;
+    }
+    assert_eq!(program.stats().s_reductions, stats.s_reductions);
+    assert_eq!(program.stats().bytes_written, 14);
+
+    // Garbage collection is counted, and keeps pool small.
+    let config = RunnerConfig {
+        gc_threshold_bytes: 16 * 1024,
+        ..RunnerConfig::default()
+    };
+    let mut program =
+        LazyKProgram::compile_with_config(include_str!("../examples/ab.lazy"), config)?;
+    program.set_output_limit(Some(2000));
+    program.run_vec(vec![])?;
+    let stats = program.stats();
+    assert!(stats.gc_cycles > 0);
+    assert!(stats.allocations > stats.peak_pool_size as u64);
+    assert_eq!(stats.bytes_written, 2000);
     Ok(())
 }
 
@@ -139,34 +500,63 @@ fn test_make_printer() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_make_compressed_printer() -> Result<()> {
+    let size = |program: &LazyKProgram| program.to_source(Style::CombCalculus).unwrap().len();
+    let texts = [
+        String::new(),
+        "abc".to_string(),
+        "Hallo Welt!\n".to_string(),
+        "abcabcabcxyzxyz abcabcabc".to_string(),
+        "ab".repeat(100),
+        include_str!("../examples/ab.lazy").to_string(),
+    ];
+    for text in texts {
+        let mut program = LazyKProgram::make_compressed_printer(text.as_bytes());
+        assert!(size(&program) <= size(&LazyKProgram::make_printer(text.as_bytes())));
+        assert_eq!(program.run_string("")?, text);
+    }
+
+    // Repeated parts are written once.
+    let text = "abcabcabcxyzxyz abcabcabc";
+    let program = LazyKProgram::make_compressed_printer(text.as_bytes());
+    assert!(size(&program) * 2 < size(&LazyKProgram::make_printer(text.as_bytes())));
+
+    // List of bytes is as deep as it is long.
+    let text = "Hello, world!\n".repeat(3000);
+    let mut program = LazyKProgram::make_compressed_printer(text.as_bytes());
+    assert_eq!(program.run_string("")?, text);
+    Ok(())
+}
+
 #[test]
 fn test_to_source() -> Result<()> {
     let text = "Hallo Welt!\n";
     let program = LazyKProgram::make_printer(text.as_bytes());
 
     // Combinator-calculus style.
-    let source = program.to_source(Style::CombCalculus);
+    let source = program.to_source(Style::CombCalculus)?;
     let expected_source = include_str!("../examples/hallo_welt_1.lazy");
     assert_eq!(source, expected_source);
     let mut program2 = LazyKProgram::compile(&source)?;
     assert_eq!(program2.run_string("")?, text);
 
     // Unlambda style.
-    let source = program.to_source(Style::Unlambda);
+    let source = program.to_source(Style::Unlambda)?;
     let expected_source = include_str!("../examples/hallo_welt_2.lazy");
     assert_eq!(source, expected_source);
     let mut program2 = LazyKProgram::compile(&source)?;
     assert_eq!(program2.run_string("")?, text);
 
     // Jot style.
-    let source = program.to_source(Style::Jot);
+    let source = program.to_source(Style::Jot)?;
     let expected_source = include_str!("../examples/hallo_welt_3.lazy");
     assert_eq!(source, expected_source);
     let mut program2 = LazyKProgram::compile(&source)?;
     assert_eq!(program2.run_string("")?, text);
 
     // Iota style.
-    let source = program.to_source(Style::Iota);
+    let source = program.to_source(Style::Iota)?;
     let expected_source = include_str!("../examples/hallo_welt_4.lazy");
     assert_eq!(source, expected_source);
     let mut program2 = LazyKProgram::compile(&source)?;
@@ -179,6 +569,600 @@ fn test_to_source() -> Result<()> {
 fn test_parses_mixed_style() -> Result<()> {
     let source = "SII``sii";
     let program = LazyKProgram::compile(source)?;
-    assert_eq!(program.to_source(Style::CombCalculus), "SII(SII)");
+    assert_eq!(program.to_source(Style::CombCalculus)?, "SII(SII)");
+    Ok(())
+}
+
+#[test]
+fn test_step_budget() -> Result<()> {
+    let mut program = LazyKProgram::compile("SII(SII)")?;
+    program.set_step_budget(Some(10000));
+    assert_error(program.run_string(""), "Reduction budget exhausted.");
+    assert_eq!(program.start_vec(vec![])?, RunOutcome::BudgetExhausted);
+    assert_eq!(program.resume(10000)?, RunOutcome::BudgetExhausted);
+    Ok(())
+}
+
+#[test]
+fn test_resume_after_budget_exhausted() -> Result<()> {
+    let source = include_str!("../examples/reverse.lazy");
+    let mut program = LazyKProgram::compile(source)?;
+    program.set_step_budget(Some(100));
+    let mut outcome = program.start_vec("Hello, world!".as_bytes().to_vec())?;
+    let mut resumes = 0;
+    while outcome == RunOutcome::BudgetExhausted {
+        outcome = program.resume(100)?;
+        resumes += 1;
+    }
+    assert!(resumes > 10);
+    assert_eq!(outcome, RunOutcome::Halted(0));
+    assert_eq!(program.take_output(), "!dlrow ,olleH".as_bytes());
+    assert_eq!(program.exit_code(), Some(0));
+    Ok(())
+}
+
+#[test]
+fn test_exit_code() -> Result<()> {
+    let source = "K(K(S(S(KS)K)(S(S(KS)K)(S(S(KS)K)(SII(S(S(KS)K)I(S(S(KS)K)I)))))))";
+    let mut program = LazyKProgram::compile(source)?;
+    assert_eq!(program.run_string("")?, "");
+    assert_eq!(program.exit_code(), Some(3));
+
+    let mut program = LazyKProgram::compile("SII(SII)")?;
+    program.set_step_budget(Some(100));
+    assert_eq!(program.start_vec(vec![])?, RunOutcome::BudgetExhausted);
+    assert_eq!(program.exit_code(), None);
+    Ok(())
+}
+
+#[test]
+fn test_rerun_after_budget_exhausted() -> Result<()> {
+    let source = include_str!("../examples/calc.lazy");
+    let mut program = LazyKProgram::compile(source)?;
+    program.set_step_budget(Some(5000));
+    assert_error(program.run_string("2+3*4"), "Reduction budget exhausted.");
+    program.set_step_budget(None);
+    assert_eq!(program.run_string("2+3*4")?, "14\n");
+    Ok(())
+}
+
+#[test]
+fn test_print_after_budget_exhausted() -> Result<()> {
+    let text = "Hello, world!";
+    let source = LazyKProgram::make_printer(text.as_bytes()).to_source(Style::CombCalculus)?;
+    let mut program = LazyKProgram::compile(&source)?;
+    program.set_step_budget(Some(50));
+    assert_error(program.run_string(""), "Reduction budget exhausted.");
+    let printed = program.to_source(Style::CombCalculus)?;
+    assert_eq!(LazyKProgram::compile(&printed)?.run_string("")?, text);
+
+    // Suspended run can be printed and optimized, and then resumed.
+    assert_eq!(program.start_vec(vec![])?, RunOutcome::BudgetExhausted);
+    let printed = program.to_source_shared(Style::Unlambda)?;
+    assert_eq!(LazyKProgram::compile(&printed)?.run_string("")?, text);
+    program.optimize()?;
+    program.set_step_budget(None);
+    assert_eq!(program.resume(u64::MAX)?, RunOutcome::Halted(0));
+    assert_eq!(program.take_output(), text.as_bytes());
+    Ok(())
+}
+
+#[test]
+fn test_lambda_bracket_abstraction() -> Result<()> {
+    let cases = [
+        (r"\x. x", "I"),
+        (r"\x y. x", "K"),
+        (r"\x y. y", "KI"),
+        (r"\x y z. x z (y z)", "S"),
+        (r"\f g x. f (g x)", "S(KS)K"),
+        (r"\f x y. f y x", "S(S(KS)(S(KK)S))(KK)"),
+        (r"\x. \y. x y", "I"),
+        (r"\x. S K", "K(SK)"),
+    ];
+    for (source, expected) in cases {
+        let program = LazyKProgram::compile(source)?;
+        assert_eq!(program.to_source(Style::CombCalculus)?, expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_lambda_program() -> Result<()> {
+    let source = r"
+        # Scott-encoded lists.
+        car = \list. list K;
+        cdr = \list. list (K I);
+        twice = \f x. f (f x);
+
+        \input. twice cdr input
+    ";
+    let mut program = LazyKProgram::compile(source)?;
+    assert_eq!(program.run_string("Hello")?, "llo");
+    assert_eq!(program.run_string("Hi")?, "");
+
+    // Variables shadow combinators and definitions.
+    let mut program = LazyKProgram::compile(r"k = K; \k. k")?;
+    assert_eq!(program.run_string("abc")?, "abc");
+    let mut program = LazyKProgram::compile(r"\s. s")?;
+    assert_eq!(program.run_string("abc")?, "abc");
+    Ok(())
+}
+
+#[test]
+fn test_lambda_parse_errors() {
+    assert_error(
+        LazyKProgram::compile(r"\. K"),
+        "1:2: Expected lambda parameters followed by '.'.",
+    );
+    assert_error(
+        LazyKProgram::compile("id = I"),
+        "1:7: Expected ';' after definition.",
+    );
+    assert_error(LazyKProgram::compile("KS; K"), "1:3: Unexpected ';'.");
+    assert_error(
+        LazyKProgram::compile(r"\x. y"),
+        "1:5: Invalid character: [y]",
+    );
+}
+
+// Checks that DOT output defines every node it has edges to, and returns number of nodes.
+fn check_dot(dot: &str) -> usize {
+    let lines: Vec<&str> = dot.lines().map(str::trim).collect();
+    let nodes: Vec<&str> = lines
+        .iter()
+        .filter(|line| line.starts_with('n') && !line.contains("->"))
+        .map(|line| line.split(' ').next().unwrap())
+        .collect();
+    for line in lines.iter().filter(|line| line.contains("->")) {
+        let target = line.split(' ').nth(2).unwrap().trim_end_matches(';');
+        assert!(nodes.contains(&target), "Undefined node {}", target);
+    }
+    nodes.len()
+}
+
+#[test]
+fn test_to_dot() -> Result<()> {
+    let program = LazyKProgram::compile("SKK")?;
+    let dot = program.to_dot();
+    assert!(dot.starts_with("digraph {\n"));
+    // Two applications, S and (shared) K.
+    assert_eq!(check_dot(&dot), 4);
+    assert_eq!(dot.matches("label=\"K\"").count(), 1);
+
+    let program = LazyKProgram::compile("x = S(K(SII)); x x")?;
+    assert_eq!(check_dot(&program.to_dot()), 8);
+    Ok(())
+}
+
+#[test]
+fn test_state_to_dot() -> Result<()> {
+    let source = include_str!("../examples/reverse.lazy");
+    let mut program = LazyKProgram::compile(source)?;
+    program.set_step_budget(Some(500));
+    assert_eq!(
+        program.start_vec("abc".as_bytes().to_vec())?,
+        RunOutcome::BudgetExhausted
+    );
+    let dot = program.state_to_dot();
+    check_dot(&dot);
+    assert!(dot.contains("\"output\" -> n"));
+    assert!(dot.contains("\"eval\" -> n"));
+    assert!(dot.contains("label=\"LazyRead\""));
+    assert_eq!(dot.matches("fillcolor=yellow").count(), 1);
+
+    // Printing doesn't disturb the run.
+    check_dot(&program.to_dot());
+    while program.resume(500)? == RunOutcome::BudgetExhausted {
+        check_dot(&program.state_to_dot());
+    }
+    assert_eq!(program.take_output(), "cba".as_bytes());
+    Ok(())
+}
+
+#[test]
+fn test_to_source_shared() -> Result<()> {
+    let text = "Hallo Welt!\n";
+    let program = LazyKProgram::make_printer(text.as_bytes());
+    assert_eq!(
+        program.to_source_shared(Style::Jot)?,
+        program.to_source(Style::Jot)?
+    );
+    for style in [Style::CombCalculus, Style::Unlambda, Style::Iota] {
+        let source = program.to_source_shared(style)?;
+        assert!(source.len() < program.to_source(style)?.len());
+        let mut program2 = LazyKProgram::compile(&source)?;
+        // Round trip preserves sharing. In Iota style, parsed program also
+        // shares the iota combinator.
+        if style != Style::Iota {
+            assert_eq!(program2.to_source_shared(style)?, source);
+        }
+        assert_eq!(program2.run_string("")?, text);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_to_source_shared_is_linear() -> Result<()> {
+    // Tree representation of x20 has 2^20 copies of x0.
+    let mut source = String::from("x0 = SKK;\n");
+    for i in 1..=20 {
+        source += &format!("x{} = x{} x{};\n", i, i - 1, i - 1);
+    }
+    source += "x20";
+    let program = LazyKProgram::compile(&source)?;
+    let shared_source = program.to_source_shared(Style::Unlambda)?;
+    assert!(shared_source.len() < 500);
+    let mut program2 = LazyKProgram::compile(&shared_source)?;
+    assert_eq!(program2.run_string("abc")?, "abc");
+    Ok(())
+}
+
+#[test]
+fn test_style_names() -> Result<()> {
+    for style in [
+        Style::CombCalculus,
+        Style::Unlambda,
+        Style::Jot,
+        Style::Iota,
+    ] {
+        assert_eq!(style.to_string().parse::<Style>()?, style);
+    }
+    assert_eq!("CC".parse::<Style>()?, Style::CombCalculus);
+    assert_eq!("Iota".parse::<Style>()?, Style::Iota);
+    assert_error("java".parse::<Style>(), "Unknown style: java.");
+    Ok(())
+}
+
+#[test]
+fn test_garbage_collection() -> Result<()> {
+    let config = RunnerConfig {
+        gc_threshold_bytes: 16 * 1024,
+        memory_limit_bytes: Some(4 * 1024 * 1024),
+        ..RunnerConfig::default()
+    };
+    let source = include_str!("../examples/primes.lazy");
+    let mut program = LazyKProgram::compile_with_config(source, config)?;
+    program.set_output_limit(Some(70));
+    let expected = "2 3 5 7 11 13 17 19 23 29 31 37 41 43 47 53 59 61 67 71 73 79 83 89 97";
+    assert_eq!(program.run_string("")?, expected.replace(" ", "\n"));
+    let source = include_str!("../examples/ab.lazy");
+    let mut program = LazyKProgram::compile_with_config(source, config)?;
+    program.set_output_limit(Some(20000));
+    assert_eq!(program.run_string("")?, "AB".repeat(10000));
+    Ok(())
+}
+
+#[test]
+fn test_memory_limit() -> Result<()> {
+    let config = RunnerConfig {
+        memory_limit_bytes: Some(1024 * 1024),
+        ..RunnerConfig::default()
+    };
+    // Expression of this program keeps growing.
+    let mut program = LazyKProgram::compile_with_config("S(SII)I(S(SII)I)", config)?;
+    assert_error(program.run_string(""), "Memory limit exceeded.");
+    // Program can be run again.
+    assert_error(program.run_string(""), "Memory limit exceeded.");
+
+    // Building expressions outside of evaluation is limited too.
+    let config = RunnerConfig {
+        memory_limit_bytes: Some(64 * 1024),
+        ..RunnerConfig::default()
+    };
+    assert_error(
+        LazyKProgram::compile_with_config(&"SKK".repeat(10000), config),
+        "Memory limit exceeded.",
+    );
+    let mut program = LazyKProgram::compile_with_config("I", config)?;
+    assert_error(
+        program.call::<_, Vec<u8>>(&vec![0u8; 10000]),
+        "Memory limit exceeded.",
+    );
+    assert_eq!(program.call::<_, Vec<u8>>(&vec![1u8, 2])?, vec![1, 2]);
+    Ok(())
+}
+
+#[test]
+fn test_compacting_garbage_collection() -> Result<()> {
+    let config = RunnerConfig {
+        gc_threshold_bytes: 16 * 1024,
+        compacting_gc: true,
+        ..RunnerConfig::default()
+    };
+    let source = include_str!("../examples/primes.lazy");
+    let mut program = LazyKProgram::compile_with_config(source, config)?;
+    let expected = "2 3 5 7 11 13 17 19 23 29 31 37 41 43 47 53 59 61 67 71 73 79 83 89 97";
+    // Program's expression is moved by the first run, but still can be run again.
+    program.set_output_limit(Some(3));
+    assert_eq!(program.run_string("")?, "2\n3");
+    program.set_output_limit(Some(70));
+    assert_eq!(program.run_string("")?, expected.replace(" ", "\n"));
+    assert_eq!(program.run_string("")?, expected.replace(" ", "\n"));
+    let source = include_str!("../examples/reverse.lazy");
+    let mut program = LazyKProgram::compile_with_config(source, config)?;
+    let input = "abcde12345".repeat(100);
+    let expected: String = input.chars().rev().collect();
+    assert_eq!(program.run_string(&input)?, expected);
+    assert_eq!(program.run_string(&input)?, expected);
+    Ok(())
+}
+
+#[test]
+fn test_garbage_collection_during_evaluation() -> Result<()> {
+    // Reverse reads whole input before printing anything. Without collecting
+    // garbage during evaluation, it needs more memory than allowed here.
+    let source = include_str!("../examples/reverse.lazy");
+    let input = "abcde12345".repeat(100);
+    let expected: String = input.chars().rev().collect();
+    for compacting_gc in [false, true] {
+        let config = RunnerConfig {
+            gc_threshold_bytes: 16 * 1024,
+            memory_limit_bytes: Some(4 * 1024 * 1024),
+            compacting_gc,
+            ..RunnerConfig::default()
+        };
+        let mut program = LazyKProgram::compile_with_config(source, config)?;
+        assert_eq!(program.run_string(&input)?, expected);
+        // Suspended evaluation is updated by compacting garbage collector.
+        program.set_step_budget(Some(10000));
+        let mut outcome = program.start_vec(input.as_bytes().to_vec())?;
+        while outcome == RunOutcome::BudgetExhausted {
+            outcome = program.resume(10000)?;
+        }
+        assert_eq!(program.take_output(), expected.as_bytes());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_decode_large_numerals() -> Result<()> {
+    let mut pool = LazyKRunner::new();
+    // Application of numerals is exponentiation: 256^2 and 100^3.
+    let x = pool.partial_apply(pool.church_char(2), pool.church_char(256))?;
+    assert_eq!(pool.church2u64(x)?, 65536);
+    let x = pool.partial_apply(pool.church_char(2), pool.church_char(256))?;
+    assert_error(pool.church2int(x), "Church numeral is too large.");
+    let x = pool.partial_apply(pool.church_char(3), pool.church_char(100))?;
+    assert_eq!(pool.church2big(x)?.to_string(), "1000000");
+    assert_error(
+        pool.church2u64(pool.k),
+        "Program's output is not a church numeral.",
+    );
+
+    let mut program = LazyKProgram::compile("\\f x. f (f (f x))")?;
+    assert_eq!(program.decode_u64()?, 3);
+    assert_eq!(program.decode_big()?, BigUint::from(3));
+    // Function is applied to more than one argument.
+    for source in ["\\f x. f f x", "\\f x. f x x", "\\f x. f ((\\y. f) x) x"] {
+        let mut program = LazyKProgram::compile(source)?;
+        assert_error(
+            program.decode_u64(),
+            "Program's output is not a church numeral.",
+        );
+        assert_error(
+            program.decode_big(),
+            "Program's output is not a church numeral.",
+        );
+    }
+
+    // Decoding 10^10 takes more reductions than the budget allows.
+    let mut program =
+        LazyKProgram::compile("ten = \\f x. f (f (f (f (f (f (f (f (f (f x))))))))); ten ten")?;
+    program.set_step_budget(Some(10000));
+    assert_error(program.decode_u64(), "Reduction budget exhausted.");
+    assert_error(program.decode_big(), "Reduction budget exhausted.");
+    Ok(())
+}
+
+#[test]
+fn test_typed_values() -> Result<()> {
+    let mut id = LazyKProgram::compile("I")?;
+    assert!(id.call::<_, bool>(&true)?);
+    assert!(!id.call::<_, bool>(&false)?);
+    assert_eq!(id.call::<_, u8>(&255u8)?, 255);
+    assert_eq!(id.call::<_, u32>(&100000u32)?, 100000);
+    assert_error(id.call::<_, u8>(&300u32), "Church numeral is too large.");
+    assert_eq!(id.call::<_, Option<u8>>(&Some(5u8))?, Some(5));
+    assert_eq!(id.call::<_, Option<u8>>(&None::<u8>)?, None);
+    assert_eq!(
+        id.call::<_, Vec<u16>>(&vec![1u16, 1000, 0])?,
+        vec![1, 1000, 0]
+    );
+    assert_eq!(id.call::<_, Vec<bool>>(&Vec::<bool>::new())?, vec![]);
+    assert_eq!(
+        id.call::<_, (u8, bool, Vec<u8>)>(&(1u8, false, vec![2u8]))?,
+        (1, false, vec![2])
+    );
+    assert_eq!(
+        id.call::<_, (u16, u8, u32, u64)>(&(300u16, 7u8, 0u32, 70000u64))?,
+        (300, 7, 0, 70000)
+    );
+    assert_eq!(id.call::<_, String>("Привет")?, "Привет");
+    let big = BigUint::from(1 << 20);
+    assert_eq!(id.call::<_, BigUint>(&big)?, big);
+    assert_error(
+        id.call::<_, bool>(&5u8),
+        "Expression is not a valid boolean.",
+    );
+
+    let mut not = LazyKProgram::compile("\\b x y. b y x")?;
+    assert!(not.call::<_, bool>(&false)?);
+    let mut add = LazyKProgram::compile("\\p. p (\\m n f x. m f (n f x))")?;
+    assert_eq!(add.call::<_, u64>(&(300u64, 1000u64))?, 1300);
+    // Maps list with `Some`.
+    let mut wrap = LazyKProgram::compile(
+        "fix = \\f. (\\x. f (x x)) (\\x. f (x x));
+         map = fix (\\map g l. l l (\\x xs n c. c (g x) (map g xs)));
+         map (\\x n s. s x)",
+    )?;
+    assert_eq!(
+        wrap.call::<_, Vec<Option<u8>>>(&vec![1u8, 2])?,
+        vec![Some(1), Some(2)]
+    );
+    // Whole programs are functions on strings.
+    let mut reverse = LazyKProgram::compile(include_str!("../examples/reverse.lazy"))?;
+    assert_eq!(reverse.call::<_, String>("abc")?, "cba");
+
+    // Budget limits the whole decoding, not every evaluation in it.
+    let mut program = LazyKProgram::compile("SII(SII)")?;
+    program.set_step_budget(Some(1000));
+    assert_error(
+        program.call::<_, bool>(&true),
+        "Reduction budget exhausted.",
+    );
+    assert_error(program.call::<_, u8>(&1u8), "Reduction budget exhausted.");
+    reverse.set_step_budget(Some(1000));
+    assert_error(
+        reverse.call::<_, String>("Hello, world!"),
+        "Reduction budget exhausted.",
+    );
+    Ok(())
+}
+
+#[test]
+fn test_apply() -> Result<()> {
+    let mut program = LazyKProgram::compile("I")?;
+    let args = [Term::S.app(Term::I.app(Term::K))];
+    let whnf = program.apply(&args, Reduction::WeakHead)?;
+    assert_eq!(program.term_to_source(&whnf, Style::CombCalculus)?, "S(IK)");
+    let normal = program.apply(&args, Reduction::Normal)?;
+    assert_eq!(program.to_term(&normal)?, Term::S.app(Term::K));
+    assert_eq!(program.term_to_source(&normal, Style::Unlambda)?, "`sk");
+    program.release(whnf);
+    program.release(normal);
+
+    let mut swap = LazyKProgram::compile("\\p f. p (\\a b. f b a)")?;
+    let pair = Term::source("\\f. f S K");
+    let result = swap.apply(&[pair, Term::K], Reduction::Normal)?;
+    assert_eq!(swap.to_term(&result)?, Term::K);
+    assert_error(
+        swap.apply(&[Term::source("(")], Reduction::Normal),
+        "1:1: Unmatched open-parenthesis.",
+    );
+
+    let mut program = LazyKProgram::compile("SII(SII)")?;
+    program.set_step_budget(Some(1000));
+    for reduction in [Reduction::WeakHead, Reduction::Normal] {
+        assert_error(program.apply(&[], reduction), "Reduction budget exhausted.");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_apply_with_garbage_collection() -> Result<()> {
+    for compacting_gc in [false, true] {
+        let config = RunnerConfig {
+            gc_threshold_bytes: 16 * 1024,
+            compacting_gc,
+            ..RunnerConfig::default()
+        };
+        let mut program = LazyKProgram::compile_with_config("\\n. n (S K K) (S K)", config)?;
+        // 2^16, as application of numerals is exponentiation.
+        let n = Term::source("two = \\f x. f (f x); two two two two");
+        let first = program.apply(&[Term::K], Reduction::Normal)?;
+        let second = program.apply(&[n], Reduction::Normal)?;
+        assert_eq!(program.to_term(&first)?, Term::S.app(Term::K).app(Term::K));
+        assert_eq!(program.to_term(&second)?, Term::S.app(Term::K));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_normalize() -> Result<()> {
+    let mut program = LazyKProgram::compile("two = \\f x. f (f x); two two")?;
+    program.normalize()?;
+    let source = program.to_source(Style::CombCalculus)?;
+    assert_eq!(source, "S(K(S(S(KS)K)I))(S(S(KS)K)I)");
+    assert_eq!(program.decode_u64()?, 4);
+
+    // Normalized program behaves the same.
+    let source = include_str!("../examples/hello_world.lazy");
+    let mut program = LazyKProgram::compile(source)?;
+    program.set_step_budget(Some(1000000));
+    program.normalize()?;
+    assert_eq!(program.run_string("")?, "Hello, world!\n");
+
+    // Program without normal form is left partially reduced.
+    let mut program = LazyKProgram::compile("K(SII(SII))")?;
+    program.set_step_budget(Some(1000));
+    assert_error(program.normalize(), "Reduction budget exhausted.");
+    let source = program.to_source(Style::CombCalculus)?;
+    assert!(LazyKProgram::compile(&source).is_ok());
+    Ok(())
+}
+
+#[test]
+fn test_optimize() -> Result<()> {
+    let mut program = LazyKProgram::compile("S(KI)(S(K(SKK))(K(K(SKS))))")?;
+    let optimization = program.optimize()?;
+    assert_eq!(program.to_source(Style::CombCalculus)?, "K(KI)");
+    assert_eq!(optimization.size_before, 13);
+    assert_eq!(optimization.size_after, 3);
+
+    // Optimized program is not changed by optimizing it again.
+    let optimization = program.optimize()?;
+    assert_eq!(optimization.size_before, optimization.size_after);
+    Ok(())
+}
+
+#[test]
+fn test_optimized_examples_behave_the_same() -> Result<()> {
+    // Example, its input and output limit (for programs with infinite output).
+    let cases = [
+        (include_str!("../examples/ab.lazy"), "", Some(100)),
+        (include_str!("../examples/calc.lazy"), "(2+3)*4", None),
+        (include_str!("../examples/hallo_welt_1.lazy"), "", None),
+        (include_str!("../examples/hallo_welt_2.lazy"), "", None),
+        (include_str!("../examples/hallo_welt_3.lazy"), "", None),
+        (include_str!("../examples/hallo_welt_4.lazy"), "", None),
+        (include_str!("../examples/hello_world.lazy"), "", None),
+        (include_str!("../examples/primes.lazy"), "", Some(70)),
+        (include_str!("../examples/quine.lazy"), "", None),
+        (include_str!("../examples/reverse.lazy"), "stressed", None),
+    ];
+    for (source, input, output_limit) in cases {
+        let mut program = LazyKProgram::compile(source)?;
+        program.set_output_limit(output_limit);
+        let expected = program.run_vec(input.as_bytes().to_vec())?;
+        let mut optimized = LazyKProgram::compile(source)?;
+        let optimization = optimized.optimize()?;
+        assert!(optimization.size_after <= optimization.size_before);
+        optimized.set_output_limit(output_limit);
+        assert_eq!(optimized.run_vec(input.as_bytes().to_vec())?, expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_to_source_short() -> Result<()> {
+    let program = LazyKProgram::compile("S(KS)K")?;
+    assert_eq!(program.to_source_short(Style::Jot)?, "11111110001111000");
+    assert_eq!(program.to_source_short(Style::Iota)?, "*i*i**i*i*i*i*ii*ii");
+    assert_eq!(program.to_source_short(Style::CombCalculus)?, "S(KS)K");
+    // Iota itself can't be written as a single `i`, which means identity.
+    let program = LazyKProgram::compile("S(SI(KS))(KK)")?;
+    let iota = program.to_source_short(Style::Iota)?;
+    assert_ne!(iota, "i");
+    // Iota applied to itself is identity.
+    let mut program = LazyKProgram::compile(&format!("*{}{}", iota, iota))?;
+    assert_eq!(program.run_string("ab")?, "ab");
+
+    // Short source is an equivalent program, which is never longer.
+    let cases = [
+        (include_str!("../examples/hello_world.lazy"), ""),
+        (include_str!("../examples/hallo_welt_3.lazy"), ""),
+        (include_str!("../examples/hallo_welt_4.lazy"), ""),
+        (include_str!("../examples/reverse.lazy"), "stressed"),
+    ];
+    for (source, input) in cases {
+        let mut program = LazyKProgram::compile(source)?;
+        let expected = program.run_string(input)?;
+        for style in [Style::Jot, Style::Iota] {
+            let short_source = program.to_source_short(style)?;
+            assert!(short_source.len() <= program.to_source(style)?.len());
+            let mut short_program = LazyKProgram::compile(&short_source)?;
+            assert_eq!(short_program.run_string(input)?, expected);
+        }
+    }
     Ok(())
 }